@@ -0,0 +1,44 @@
+use std::hint::black_box;
+
+use aqua_shuffle_sim::{distribute_water, run_sim, BatchScheduler, BatchSelector, MeanMix, SelectionWeights, ShuffleModel, SimulationConfig, Summation, SHUFFLE_SIZE, VECTOR_LENGTH};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rand::rngs::{SmallRng, StdRng};
+use rand::{Rng, SeedableRng};
+
+/// A single shuffle on the default parameters, with 25% of the cups corrupted, drawing the batch from `rng`
+fn bench_distribute_water_with<R: Rng + SeedableRng>(c: &mut Criterion, name: &str) {
+    let mut rng = R::seed_from_u64(0);
+    let corrupted: Vec<bool> = (0..VECTOR_LENGTH).map(|index| index != 0 && rng.gen_bool(0.25)).collect();
+    let mut cups = vec![0.0; VECTOR_LENGTH];
+    cups[0] = 1.0;
+
+    let mut batches = BatchSelector::new(BatchScheduler::RandomIid, SelectionWeights::Uniform);
+    let rule = MeanMix { mixing_factor: 1.0, summation: Summation::Naive };
+
+    let mut group = c.benchmark_group("distribute_water");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function(name, |b| {
+        b.iter(|| distribute_water(black_box(&mut cups), &corrupted, 0.0, SHUFFLE_SIZE, ShuffleModel::FullAverage, &rule, f64::INFINITY, 0.0, Summation::Naive, &mut 0.0, &mut 0.0, &mut batches, &mut rng).len())
+    });
+    group.finish();
+}
+
+/// Batch selection dominates a shuffle, so this is where the generator matters most
+fn bench_distribute_water(c: &mut Criterion) {
+    bench_distribute_water_with::<StdRng>(c, "default");
+    bench_distribute_water_with::<SmallRng>(c, "small_rng");
+}
+
+/// A scaled-down simulation of one threshold, running every repetition for all rounds
+fn bench_run_sim(c: &mut Criterion) {
+    let config = SimulationConfig { repetitions: 100, max_shuffles: 500, early_exit: false, ..SimulationConfig::default() };
+
+    let mut group = c.benchmark_group("run_sim");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((config.repetitions * config.max_shuffles) as u64));
+    group.bench_function("100x500", |b| b.iter(|| run_sim(black_box(&config), 0.1).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_distribute_water, bench_run_sim);
+criterion_main!(benches);
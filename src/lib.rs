@@ -0,0 +1,2402 @@
+//! Simulation of the AquaShuffle shuffling strategy for Whisk.
+//!
+//! Every cup holds some amount of water, and a single target cup starts out holding all of it.
+//! Each shuffle picks a random batch of cups and evenly distributes the water of its honest
+//! (uncorrupted) cups among them. The target is hidden once no cup holds more than `target_eps`.
+//!
+//! When several targets are tracked, each of them has its own water, which is mixed by the same
+//! shuffles, and a repetition only succeeds once all of them are hidden.
+
+use rand::{seq::{index, IteratorRandom, SliceRandom}, Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use rand_chacha::ChaCha20Rng;
+use rand_distr::{Binomial, Distribution, Poisson};
+use log::{debug, trace, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::ops::{ControlFlow, Range};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const VECTOR_LENGTH: usize = 2_usize.pow(14);
+
+// Size of one local shuffle
+pub const SHUFFLE_SIZE: usize = 128;
+
+// Upper bound on number of rounds in protocol execution; despite the name, a round does
+// `shuffles_per_round` shuffles, so this only counts shuffles if that is 1
+pub const MAX_SHUFFLES: usize = 4000;
+
+// Number of repetitions over which the average benchmark outcomes are computed
+pub const NUMBER_OF_REPETITIONS: usize = 1000;
+
+/// Parameters of a simulation that are shared across corruption thresholds
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SimulationConfig {
+    /// Number of cups (commitments) in the shuffled vector
+    pub vector_length: usize,
+    /// Size of one local shuffle
+    pub shuffle_size: usize,
+    /// How the size of every shuffle is drawn; only [`SizeDist::Constant`] uses `shuffle_size`
+    pub shuffle_size_dist: SizeDist,
+    /// Upper bound on number of rounds (of `shuffles_per_round` shuffles each) in protocol execution
+    pub max_shuffles: usize,
+    /// Number of shuffles in a round, before the targets are checked for hiding
+    pub shuffles_per_round: usize,
+    /// Number of rounds at the start that only shuffle: the targets are not checked for hiding before
+    /// round `burn_in + 1`, and rounds are still counted from the first one
+    pub burn_in: usize,
+    /// Draw the batches of every repetition from the same schedule, so that repetitions only differ in their
+    /// corruption (common random numbers), see [`FIXED_SCHEDULE_STREAM`]
+    pub fixed_schedule: bool,
+    /// Number of repetitions over which the average benchmark outcomes are computed
+    pub repetitions: usize,
+    /// Number of repetitions of a threshold that [`run_sim`] simulates at the same time, or all of them if `None`;
+    /// chunks that are done early keep their cups until the others catch up, so they do not take less memory
+    pub repetition_chunk: Option<usize>,
+    /// Base seed from which the RNG of every repetition is derived
+    pub seed: u64,
+    /// Cups whose hiding is tracked; these are never corrupted up front
+    pub target_cups: Vec<usize>,
+    /// When a repetition counts as successful
+    pub success_criterion: SuccessCriterion,
+    /// In which rounds a repetition counts as successful
+    pub success_counting: SuccessCounting,
+    /// How the adversary picks which cups to corrupt
+    pub adversary: AdversaryStrategy,
+    /// Let an adaptive adversary corrupt the target cups as well
+    pub allow_target_corruption: bool,
+    /// Open the target cups themselves in one round, to measure how often they were hidden by then
+    pub target_opening: Option<TargetOpening>,
+    /// How far each shuffle moves the water of a cup towards the batch average, in `(0, 1]`
+    pub mixing_factor: f64,
+    /// Fraction of the water of a batch that a faulty shuffler loses, in `[0, 1)`
+    pub leak_rate: f64,
+    /// Most water of a target that a cup can hold after a shuffle, unbounded if `None`; the rest spills and is lost
+    pub cup_capacity: Option<f64>,
+    /// How a shuffle mixes the water among the honest cups of its batch
+    pub shuffle_model: ShuffleModel,
+    /// The rule that mixes the water of every group of honest cups of [`shuffle_model`](Self::shuffle_model)
+    pub mix_rule: MixRuleKind,
+    /// How the water of a batch, and of all cups for `track_mass`, is summed
+    pub summation: Summation,
+    /// Stop shuffling once the targets are hidden in every repetition in the same round
+    pub early_exit: bool,
+    /// Fraction of repetitions, in `(0, 1]`, that must have the targets hidden in the success round
+    pub success_probability: f64,
+    /// Where the water of every target is at the start of a repetition
+    pub initial_distribution: InitialDistribution,
+    /// Numerator of `target_eps`, which is this divided by the number of honest cups
+    pub eps_numerator: f64,
+    /// Use this as `target_eps` for every threshold, instead of deriving it from `eps_numerator`
+    pub target_eps: Option<f64>,
+    /// Absolute slack of the hiding check, which passes once the water is below `target_eps` plus this.
+    /// It is meant to absorb rounding residue just above the target level, which the model leaves where it
+    /// is, unlike a larger `target_eps`, which changes what counts as hidden in the model itself.
+    pub eps_tolerance: f64,
+    /// How likely each cup is to be picked for a shuffle batch
+    pub selection_weights: SelectionWeights,
+    /// How the cups of successive shuffle batches are chosen
+    pub batch_scheduler: BatchScheduler,
+    /// Corrupt exactly these cups in every repetition, instead of a random set
+    pub corrupted_cups: Option<Vec<usize>>,
+    /// Corrupt this many random cups, instead of the given fraction of them
+    pub corrupted_count: Option<usize>,
+    /// How the number of random corrupted cups varies between repetitions around the nominal count
+    pub corruption_spread: CorruptionSpread,
+    /// Number of bootstrap resamples for the confidence interval of the mean hiding round, none if 0
+    pub bootstrap_resamples: usize,
+    /// Check after every round that the water of every target adds up to its initial amount, once the
+    /// water that was zeroed in corrupted cups or leaked is accounted for, and report the largest drift
+    pub track_mass: bool,
+}
+
+/// Where the water of a target is at the start of a repetition
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum InitialDistribution {
+    /// All water is in the target cup
+    SinglePoint,
+    /// The water is split evenly across this many random honest cups
+    Uniform(usize),
+    /// The given cups hold the given amounts of water; they are never corrupted up front
+    Custom(Vec<(usize, f64)>),
+}
+
+/// How a shuffle mixes the water among the honest cups of its batch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ShuffleModel {
+    /// All honest cups of the batch are mixed together
+    FullAverage,
+    /// Only honest cups of the batch in the same window of `window` adjacent cups (`0..window`,
+    /// `window..2*window`, ...) are mixed together, modelling locality-limited shufflers
+    WindowedAverage { window: usize },
+}
+
+/// Which [`MixRule`] a shuffle applies, with the `mixing_factor` and `summation` of the configuration
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum MixRuleKind {
+    /// [`MeanMix`], which every fixed-seed result was computed with
+    #[default]
+    Mean,
+    /// [`PairwiseSwapMix`], which ignores the `mixing_factor`
+    PairwiseSwap,
+}
+
+/// A rule for how a shuffle redistributes the water of the honest cups of a batch
+pub trait MixRule {
+    /// Replace the water of the cups in `batch`, in the order they were drawn, with their water after the shuffle,
+    /// drawing any random choices of the shuffle from `rng`
+    fn mix(&self, cups: &mut [f64], batch: &[usize], rng: &mut dyn RngCore);
+}
+
+/// Move every cup `mixing_factor` of the way towards the average of the batch, see [`mix_water`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeanMix {
+    /// How far every cup moves towards the average, in `(0, 1]`
+    pub mixing_factor: f64,
+    /// How the water of the batch is summed
+    pub summation: Summation,
+}
+
+impl MixRule for MeanMix {
+    fn mix(&self, cups: &mut [f64], batch: &[usize], _rng: &mut dyn RngCore) {
+        mix_water(cups, batch, self.mixing_factor, self.summation);
+    }
+}
+
+/// Pair up the cups of the batch in the order they were drawn, and swap the water of the cups of every
+/// pair on the toss of a fair coin, like a riffle of two cards, leaving the last cup of an odd batch as it is.
+///
+/// Unlike [`MeanMix`] this moves the water without spreading it, so a target that starts out in a single
+/// cup is never hidden in any one repetition: its water only spreads over the cups it may have gone to
+/// across repetitions. The water of every pair is conserved exactly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PairwiseSwapMix;
+
+impl MixRule for PairwiseSwapMix {
+    fn mix(&self, cups: &mut [f64], batch: &[usize], rng: &mut dyn RngCore) {
+        for pair in batch.chunks_exact(2) {
+            if rng.gen_bool(0.5) {
+                cups.swap(pair[0], pair[1]);
+            }
+        }
+    }
+}
+
+/// How sums of water over many cups are accumulated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Summation {
+    /// Add up the water one cup after the other, as fixed-seed results were computed with
+    #[default]
+    Naive,
+    /// Compensate for the rounding error of every addition with a [`KahanSum`], which keeps large
+    /// batches and long vectors accurate, at the cost of a few more operations per cup
+    Kahan,
+}
+
+impl Summation {
+    /// Return the sum of `values`, accumulated this way
+    pub fn sum(self, values: impl IntoIterator<Item = f64>) -> f64 {
+        match self {
+            Summation::Naive => values.into_iter().sum(),
+            Summation::Kahan => values.into_iter().collect::<KahanSum>().total(),
+        }
+    }
+}
+
+/// Running sum with Kahan's compensated summation.
+///
+/// The low-order bits that every addition rounds off are carried over into the next one, so the
+/// error of the total stays at a few ulps however many values are added, where it grows with their
+/// number for naive summation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KahanSum {
+    sum: f64,
+    /// Negated rounding error of the last addition, to be subtracted from the next value
+    compensation: f64,
+}
+
+impl KahanSum {
+    /// Add `value` to the sum
+    pub fn add(&mut self, value: f64) {
+        let corrected = value - self.compensation;
+        let sum = self.sum + corrected;
+        // What `corrected` lost in the addition, recovered algebraically
+        self.compensation = (sum - self.sum) - corrected;
+        self.sum = sum;
+    }
+
+    /// The sum of all values added so far
+    pub fn total(&self) -> f64 {
+        self.sum
+    }
+}
+
+impl FromIterator<f64> for KahanSum {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut sum = KahanSum::default();
+        for value in values {
+            sum.add(value);
+        }
+        sum
+    }
+}
+
+/// Distribution of the number of cups of every shuffle, drawn anew for each of them
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum SizeDist {
+    /// Always `shuffle_size` cups
+    Constant,
+    /// Uniformly between `min` and `max` cups (inclusive)
+    Uniform { min: usize, max: usize },
+    /// A Poisson number of cups with the given mean, but at least one and at most all of them
+    Poisson { mean: f64 },
+}
+
+impl SizeDist {
+    /// Draw the size of the next shuffle of a vector of `vector_length` cups
+    pub fn sample<R: Rng>(&self, shuffle_size: usize, vector_length: usize, rng: &mut R) -> usize {
+        match *self {
+            // No random numbers are drawn, so that fixed-seed results are those of a constant size
+            SizeDist::Constant => shuffle_size,
+            SizeDist::Uniform { min, max } => rng.gen_range(min..=max),
+            SizeDist::Poisson { mean } => {
+                let size: f64 = Poisson::new(mean).unwrap().sample(rng);
+                (size as usize).clamp(1, vector_length)
+            }
+        }
+    }
+}
+
+/// How the cups of successive shuffle batches are chosen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum BatchScheduler {
+    /// Every batch is drawn independently at random, so successive batches may overlap
+    RandomIid,
+    /// Batches tile a random permutation of all cups, so that every cup is shuffled exactly once per
+    /// epoch before a new permutation is drawn
+    RoundRobinDisjoint,
+}
+
+/// How likely each cup is to be picked for a shuffle batch, modelling shufflers that favor low cup indices
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum SelectionWeights {
+    /// Every cup is equally likely
+    Uniform,
+    /// Cup `i` of `n` has weight `n - i`
+    Linear,
+    /// Cup `i` has weight `ratio^i`, with `ratio` in `(0, 1]`
+    Geometric { ratio: f64 },
+}
+
+impl SelectionWeights {
+    /// Relative weight of cup `index` in a vector of `vector_length` cups
+    pub fn weight(&self, vector_length: usize, index: usize) -> f64 {
+        match *self {
+            SelectionWeights::Uniform => 1.0,
+            SelectionWeights::Linear => (vector_length - index) as f64,
+            SelectionWeights::Geometric { ratio } => ratio.powi(index as i32),
+        }
+    }
+}
+
+/// When the targets of a repetition count as hidden
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum SuccessCriterion {
+    /// No cup holds `target_eps` or more of the water of any target
+    Hidden,
+    /// The targets are secrets that must be unlinkable from each other: in every cup, the water of
+    /// any two targets differs by less than `target_eps`. Needs at least two distinct targets.
+    Unlinkable,
+}
+
+/// How many cups are corrupted in each repetition, modelling uncertainty about the budget of the adversary
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum CorruptionSpread {
+    /// Exactly the nominal count in every repetition
+    Fixed,
+    /// Every cup that may be corrupted is, independently, with the probability that gives the nominal count on average
+    Binomial,
+    /// A Poisson number of cups with the nominal count as mean, capped at the cups that may be corrupted
+    Poisson,
+}
+
+impl CorruptionSpread {
+    /// Draw the number of corrupted cups of one repetition, out of `candidates` cups that may be corrupted
+    pub fn sample<R: Rng>(&self, nominal: usize, candidates: usize, rng: &mut R) -> usize {
+        let nominal = nominal.min(candidates);
+        if nominal == 0 {
+            return 0;
+        }
+        match *self {
+            CorruptionSpread::Fixed => nominal,
+            CorruptionSpread::Binomial => {
+                let p = nominal as f64 / candidates as f64;
+                Binomial::new(candidates as u64, p).unwrap().sample(rng) as usize
+            }
+            CorruptionSpread::Poisson => {
+                let count: f64 = Poisson::new(nominal as f64).unwrap().sample(rng);
+                (count as usize).min(candidates)
+            }
+        }
+    }
+}
+
+/// When the adversary opens the target cups, which are otherwise never corrupted up front
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct TargetOpening {
+    /// (1-indexed) round after whose shuffles the targets are opened, at most `max_shuffles`
+    pub round: usize,
+    /// Probability, in `(0, 1]`, with which a repetition has its targets opened in that round
+    pub probability: f64,
+}
+
+/// In which rounds a repetition counts towards `successes_in_round`, and so towards the success round
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum SuccessCounting {
+    /// In every round in which the targets are hidden, even if they are not in a later round
+    PerRound,
+    /// In every round from its hiding round on, i.e. only once the targets stay hidden until the last
+    /// simulated round (which is the success round if the simulation exits early)
+    StaysHidden,
+}
+
+/// How the adversary picks which cups to corrupt
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum AdversaryStrategy {
+    /// Corrupt a random set of cups once, at the start of every repetition
+    StaticRandom,
+    /// Start like `StaticRandom`, but every `interval` rounds move `portion` of the corruption
+    /// budget from random corrupted cups to the honest cups currently holding the most water
+    AdaptiveGreedy { interval: usize, portion: f64 },
+    /// Start without corrupted cups and open `rate` random honest cups before every round, until
+    /// the corruption budget is used up. Opened cups stay corrupted and lose their water, unless
+    /// `redistribute` is set: then it is poured into the honest cups of the next batch instead.
+    ProgressiveOpening { rate: usize, redistribute: bool },
+    /// Never corrupt a cup for good, but corrupt every cup of a batch independently for that shuffle
+    /// only, with the fraction of the corruption budget in all cups as probability. The expected
+    /// corruption of a batch is then (nearly) that of `StaticRandom`, but the corrupted cups move.
+    Transient,
+    /// Start without corrupted cups and, after every round, corrupt the honest cup holding the most
+    /// water (summed over the targets), until the corruption budget is used up. The water of that cup
+    /// is lost, and target cups are only corrupted if `allow_target_corruption` is set.
+    CorruptMaxEachRound,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            vector_length: VECTOR_LENGTH,
+            shuffle_size: SHUFFLE_SIZE,
+            shuffle_size_dist: SizeDist::Constant,
+            max_shuffles: MAX_SHUFFLES,
+            shuffles_per_round: 1,
+            burn_in: 0,
+            fixed_schedule: false,
+            repetitions: NUMBER_OF_REPETITIONS,
+            repetition_chunk: None,
+            seed: 0,
+            target_cups: vec![0],
+            adversary: AdversaryStrategy::StaticRandom,
+            allow_target_corruption: false,
+            target_opening: None,
+            mixing_factor: 1.0,
+            leak_rate: 0.0,
+            cup_capacity: None,
+            shuffle_model: ShuffleModel::FullAverage,
+            mix_rule: MixRuleKind::Mean,
+            summation: Summation::Naive,
+            success_criterion: SuccessCriterion::Hidden,
+            success_counting: SuccessCounting::PerRound,
+            early_exit: true,
+            success_probability: 1.0,
+            initial_distribution: InitialDistribution::SinglePoint,
+            eps_numerator: 4.0,
+            target_eps: None,
+            eps_tolerance: 0.0,
+            selection_weights: SelectionWeights::Uniform,
+            batch_scheduler: BatchScheduler::RandomIid,
+            corrupted_cups: None,
+            corrupted_count: None,
+            corruption_spread: CorruptionSpread::Fixed,
+            bootstrap_resamples: 0,
+            track_mass: false,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// Check that the configuration can be simulated
+    pub fn validate(&self) -> Result<(), SimError> {
+        if self.vector_length == 0 {
+            return Err(SimError::EmptyVector);
+        }
+        if self.repetitions == 0 {
+            return Err(SimError::NoRepetitions);
+        }
+        if self.repetition_chunk == Some(0) {
+            return Err(SimError::EmptyRepetitionChunk);
+        }
+        if self.shuffles_per_round == 0 {
+            return Err(SimError::NoShufflesPerRound);
+        }
+        if self.burn_in >= self.max_shuffles && self.max_shuffles > 0 {
+            return Err(SimError::BurnInTooLong { burn_in: self.burn_in, max_shuffles: self.max_shuffles });
+        }
+        if self.shuffle_model == (ShuffleModel::WindowedAverage { window: 0 }) {
+            return Err(SimError::EmptyWindow);
+        }
+        if self.shuffle_size == 0 {
+            return Err(SimError::EmptyShuffle);
+        }
+        if self.shuffle_size > self.vector_length {
+            return Err(SimError::ShuffleSizeTooLarge { shuffle_size: self.shuffle_size, vector_length: self.vector_length });
+        }
+        match self.shuffle_size_dist {
+            SizeDist::Constant => {}
+            SizeDist::Uniform { min, max } if min == 0 || min > max => return Err(SimError::InvalidSizeRange { min, max }),
+            SizeDist::Uniform { max, .. } if max > self.vector_length => {
+                return Err(SimError::ShuffleSizeTooLarge { shuffle_size: max, vector_length: self.vector_length });
+            }
+            SizeDist::Uniform { .. } => {}
+            SizeDist::Poisson { mean } if !(mean > 0.0 && mean.is_finite()) => return Err(SimError::InvalidMeanShuffleSize(mean)),
+            SizeDist::Poisson { .. } => {}
+        }
+        if self.target_cups.is_empty() {
+            return Err(SimError::NoTargets);
+        }
+        if self.success_criterion == SuccessCriterion::Unlinkable {
+            let mut secrets = self.target_cups.clone();
+            secrets.sort_unstable();
+            secrets.dedup();
+            if secrets.len() < 2 {
+                return Err(SimError::TooFewSecrets);
+            }
+        }
+        if self.batch_scheduler == BatchScheduler::RoundRobinDisjoint && self.selection_weights != SelectionWeights::Uniform {
+            return Err(SimError::WeightedDisjointBatches);
+        }
+
+        let custom_cups = match &self.initial_distribution {
+            InitialDistribution::Custom(water) => water.iter().map(|&(cup, _)| cup).collect(),
+            _ => Vec::new(),
+        };
+        let corrupted_cups = self.corrupted_cups.iter().flatten();
+        if let Some(&cup) = self.target_cups.iter().chain(&custom_cups).chain(corrupted_cups).find(|&&cup| cup >= self.vector_length) {
+            return Err(SimError::CupOutOfRange { cup, vector_length: self.vector_length });
+        }
+        // Averaging sums the water of a batch, so the total has to stay finite as well
+        if let InitialDistribution::Custom(water) = &self.initial_distribution {
+            let mut total = 0.0;
+            for &(cup, amount) in water {
+                if !(amount >= 0.0 && amount.is_finite()) {
+                    return Err(SimError::InvalidInitialWater { cup, water: amount });
+                }
+                total += amount;
+                if !total.is_finite() {
+                    return Err(SimError::InitialWaterOverflow { cup });
+                }
+            }
+        }
+        if self.corrupted_cups.is_some() && self.corruption_spread != CorruptionSpread::Fixed {
+            return Err(SimError::SpreadOfFixedCups);
+        }
+        if let Some(corrupted) = self.corrupted_cups.as_ref().map(Vec::len).or(self.corrupted_count) {
+            if corrupted >= self.vector_length {
+                return Err(SimError::NoHonestCups { corrupted, vector_length: self.vector_length });
+            }
+        }
+        if let Some(target_eps) = self.target_eps.filter(|target_eps| !(target_eps > &0.0 && target_eps < &1.0)) {
+            return Err(SimError::InvalidTargetEps(target_eps));
+        }
+        if !(self.eps_tolerance >= 0.0 && self.eps_tolerance.is_finite()) {
+            return Err(SimError::InvalidEpsTolerance(self.eps_tolerance));
+        }
+        if !(self.success_probability > 0.0 && self.success_probability <= 1.0) {
+            return Err(SimError::InvalidSuccessProbability(self.success_probability));
+        }
+        if !(0.0..1.0).contains(&self.leak_rate) {
+            return Err(SimError::InvalidLeakRate(self.leak_rate));
+        }
+        if let Some(capacity) = self.cup_capacity.filter(|capacity| capacity.is_nan() || *capacity <= 0.0) {
+            return Err(SimError::InvalidCupCapacity(capacity));
+        }
+        if let Some(TargetOpening { round, probability }) = self.target_opening {
+            if round == 0 || round > self.max_shuffles {
+                return Err(SimError::OpeningRoundOutOfRange { round, max_shuffles: self.max_shuffles });
+            }
+            if !(probability > 0.0 && probability <= 1.0) {
+                return Err(SimError::InvalidOpeningProbability(probability));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of repetitions of a threshold that [`run_sim`] simulates at the same time: `repetition_chunk` if given,
+    /// or all of them
+    pub fn repetitions_at_once(&self) -> usize {
+        self.repetition_chunk.unwrap_or(self.repetitions).clamp(1, self.repetitions.max(1))
+    }
+}
+
+/// Reason why a simulation cannot be run
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimError {
+    /// The vector has no cups
+    EmptyVector,
+    /// No repetitions were requested, so there is nothing to average over
+    NoRepetitions,
+    /// Repetitions can only be simulated in chunks of at least one
+    EmptyRepetitionChunk,
+    /// Every round needs at least one shuffle
+    NoShufflesPerRound,
+    /// The burn-in covers every round, so the targets would never be checked for hiding
+    BurnInTooLong { burn_in: usize, max_shuffles: usize },
+    /// A windowed shuffle needs windows of at least one cup
+    EmptyWindow,
+    /// A shuffle of no cups never mixes any water, so the targets would never be hidden
+    EmptyShuffle,
+    /// A shuffle batch cannot hold more cups than the vector
+    ShuffleSizeTooLarge { shuffle_size: usize, vector_length: usize },
+    /// A uniform range of shuffle sizes must be non-empty and not include empty shuffles
+    InvalidSizeRange { min: usize, max: usize },
+    /// The mean of Poisson distributed shuffle sizes is not positive and finite
+    InvalidMeanShuffleSize(f64),
+    /// There is no target cup whose hiding could be tracked
+    NoTargets,
+    /// Unlinkability is only defined between at least two distinct secrets
+    TooFewSecrets,
+    /// Disjoint batches tile all cups, so they cannot favor some of them
+    WeightedDisjointBatches,
+    /// A target, initially filled or corrupted cup is not part of the vector
+    CupOutOfRange { cup: usize, vector_length: usize },
+    /// A cup of a custom distribution starts out with negative, infinite or NaN water
+    InvalidInitialWater { cup: usize, water: f64 },
+    /// The water of a custom distribution adds up to more than a number can hold, from this cup on
+    InitialWaterOverflow { cup: usize },
+    /// An explicit set of corrupted cups has a fixed size
+    SpreadOfFixedCups,
+    /// At least one cup has to stay honest
+    NoHonestCups { corrupted: usize, vector_length: usize },
+    /// The corruption threshold is not in `[0, 1)`
+    InvalidFraction(f64),
+    /// A fixed target water level is not in `(0, 1)`
+    InvalidTargetEps(f64),
+    /// The tolerance of the hiding check is negative or not finite
+    InvalidEpsTolerance(f64),
+    /// The success probability is not in `(0, 1]`
+    InvalidSuccessProbability(f64),
+    /// The leak rate is not in `[0, 1)`
+    InvalidLeakRate(f64),
+    /// A cup must be able to hold some water
+    InvalidCupCapacity(f64),
+    /// The targets can only be opened in a simulated round
+    OpeningRoundOutOfRange { round: usize, max_shuffles: usize },
+    /// The probability of opening the targets is not in `(0, 1]`
+    InvalidOpeningProbability(f64),
+    /// Some cup held infinite or NaN water after this round, so no hiding decision can be trusted
+    NonFiniteWater { round: usize },
+    /// A saved repetition is not of a vector of this many cups with this many targets
+    SnapshotMismatch { vector_length: usize, targets: usize },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::EmptyVector => write!(f, "vector length must be at least 1"),
+            SimError::NoRepetitions => write!(f, "repetitions must be at least 1"),
+            SimError::EmptyRepetitionChunk => write!(f, "repetition chunk must be at least 1"),
+            SimError::NoShufflesPerRound => write!(f, "shuffles per round must be at least 1"),
+            SimError::BurnInTooLong { burn_in, max_shuffles } => {
+                write!(f, "burn-in ({burn_in} rounds) must be shorter than the simulation ({max_shuffles} rounds)")
+            }
+            SimError::EmptyWindow => write!(f, "shuffle window must be at least 1"),
+            SimError::EmptyShuffle => write!(f, "shuffle size must be at least 1"),
+            SimError::ShuffleSizeTooLarge { shuffle_size, vector_length } => {
+                write!(f, "shuffle size ({shuffle_size}) cannot exceed vector length ({vector_length})")
+            }
+            SimError::InvalidSizeRange { min, max } => write!(f, "shuffle sizes {min} to {max} must be a non-empty range from at least 1"),
+            SimError::InvalidMeanShuffleSize(mean) => write!(f, "mean shuffle size ({mean}) must be positive"),
+            SimError::NoTargets => write!(f, "at least one target cup is needed"),
+            SimError::TooFewSecrets => write!(f, "at least two distinct secret cups are needed"),
+            SimError::WeightedDisjointBatches => write!(f, "disjoint batches cannot use selection weights"),
+            SimError::CupOutOfRange { cup, vector_length } => {
+                write!(f, "cup ({cup}) must be smaller than vector length ({vector_length})")
+            }
+            SimError::InvalidInitialWater { cup, water } => write!(f, "initial water of cup {cup} ({water}) must be finite and non-negative"),
+            SimError::InitialWaterOverflow { cup } => write!(f, "initial water overflows at cup {cup}, the total must be finite"),
+            SimError::SpreadOfFixedCups => write!(f, "explicitly corrupted cups cannot vary between repetitions"),
+            SimError::NoHonestCups { corrupted, vector_length } => {
+                write!(f, "corrupted cups ({corrupted}) must be fewer than vector length ({vector_length})")
+            }
+            SimError::InvalidFraction(fraction) => write!(f, "corrupted fraction ({fraction}) must be in [0, 1)"),
+            SimError::InvalidTargetEps(target_eps) => write!(f, "target eps ({target_eps}) must be in (0, 1)"),
+            SimError::InvalidEpsTolerance(tolerance) => write!(f, "eps tolerance ({tolerance}) must be at least 0 and finite"),
+            SimError::InvalidSuccessProbability(probability) => write!(f, "success probability ({probability}) must be in (0, 1]"),
+            SimError::InvalidLeakRate(leak_rate) => write!(f, "leak rate ({leak_rate}) must be in [0, 1)"),
+            SimError::InvalidCupCapacity(capacity) => write!(f, "cup capacity ({capacity}) must be positive"),
+            SimError::OpeningRoundOutOfRange { round, max_shuffles } => {
+                write!(f, "target opening round ({round}) must be between 1 and the maximum number of rounds ({max_shuffles})")
+            }
+            SimError::InvalidOpeningProbability(probability) => write!(f, "target opening probability ({probability}) must be in (0, 1]"),
+            SimError::NonFiniteWater { round } => write!(f, "water became infinite or NaN in round {round}"),
+            SimError::SnapshotMismatch { vector_length, targets } => {
+                write!(f, "saved state does not match a vector of {vector_length} cups with {targets} targets")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+/// Outcome of the simulation of a single corruption threshold.
+///
+/// JSON has no numbers that are not finite, so `serde_json` writes them as `null`. Such a `null` reads
+/// back as NaN, or as `None` for values that are optional anyway.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationResult {
+    /// Number of cups (commitments) in the shuffled vector
+    pub vector_length: usize,
+    /// Size of one local shuffle
+    pub shuffle_size: usize,
+    /// Fraction of corrupted cups
+    pub fraction_corrupted: f64,
+    /// Number of corrupted cups
+    pub corrupted_commitments: usize,
+    /// Numerator of `target_eps`, which is this divided by the number of honest cups, or `None` if
+    /// `target_eps` was given directly
+    pub eps_numerator: Option<f64>,
+    /// Water level below which the target counts as hidden
+    pub target_eps: f64,
+    /// First (1-indexed) round in which the targets were hidden in every repetition, `None` if never
+    pub success_round: Option<usize>,
+    /// First round in which each of [`PROBABILITY_LEVELS`] of the repetitions had the targets hidden,
+    /// counted as `successes_in_round` is, `None` for levels that were never reached
+    pub probability_rounds: Vec<Option<usize>>,
+    /// Number of shuffles up to and including the success round, i.e. `success_round` times
+    /// `config.shuffles_per_round`, which is what hiding costs the protocol
+    pub success_shuffles: Option<usize>,
+    /// Number of repetitions in which the target was hidden, where entry `t` is round `t+1`, counted
+    /// as `config.success_counting` says
+    pub successes_in_round: Vec<u64>,
+    /// Number of rounds, summed over the repetitions, in which the targets were hidden but not yet
+    /// for good, i.e. how many successes counting every round adds to counting only lasting hiding
+    pub transient_successes: u64,
+    /// Mean over repetitions of the number of rounds in which the targets went from not hidden to hidden
+    /// or back, which is 1 for a repetition that hid them once and for good. Repetitions whose water
+    /// oscillates around `target_eps` cross it many times, and are better judged by lasting hiding.
+    #[serde(deserialize_with = "nan_if_null")]
+    pub mean_crossings: f64,
+    /// For every repetition, the first round after which the targets stayed hidden until the last
+    /// simulated round (which is the success round if the simulation exited early)
+    pub hiding_rounds: Vec<Option<usize>>,
+    /// Spread of the rounds in which the individual repetitions hid the target for good
+    pub hiding_round_stats: Option<RoundStats>,
+    /// Highest water level of any cup in any repetition in the success round
+    pub max_water_at_success: Option<f64>,
+    /// Ratio of `max_water_at_success` to `target_eps`, i.e. how much margin the hiding had
+    pub eps_ratio: Option<f64>,
+    /// Fraction of repetitions in which the targets were hidden in the last simulated round
+    #[serde(deserialize_with = "nan_if_null")]
+    pub final_success_probability: f64,
+    /// Number of cups holding more than `target_eps` of the water of a target, summed over targets
+    /// and repetitions, where entry `t` is round `t+1`
+    pub cups_above_eps_in_round: Vec<u64>,
+    /// Mean over repetitions of the largest difference between the water of two targets in any cup,
+    /// where entry `t` is round `t+1` (always 0 for a single target)
+    #[serde(deserialize_with = "nan_if_null_in_series")]
+    pub secret_difference_in_round: Vec<f64>,
+    /// Mean [`water_entropy`] of the honest cups over targets and repetitions, in the last simulated
+    /// round (the success round, unless early exit is disabled)
+    #[serde(deserialize_with = "nan_if_null")]
+    pub entropy: f64,
+    /// Mean [`water_gini`] of the honest cups over targets and repetitions, in the last simulated round
+    #[serde(deserialize_with = "nan_if_null")]
+    pub gini: f64,
+    /// Mean number of honest cups that a shuffle actually mixed, over all shuffles of all repetitions
+    #[serde(deserialize_with = "nan_if_null")]
+    pub mean_honest_per_shuffle: f64,
+    /// Largest drift of the tracked water of any target from its initial water, over all rounds and
+    /// repetitions, if `config.track_mass` is set; anything beyond rounding errors is a bug
+    pub max_mass_drift: Option<f64>,
+    /// Number of repetitions whose targets were opened, see `config.target_opening`
+    pub targets_opened: u64,
+    /// Number of repetitions whose targets were already hidden when they were opened
+    pub safe_when_opened: u64,
+    /// Bootstrapped 95% confidence interval of the mean hiding round, if resamples were requested
+    pub mean_round_interval: Option<(f64, f64)>,
+}
+
+/// Read a number that was not finite, and so was written to JSON as `null`, back as NaN
+fn nan_if_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+/// Like [`nan_if_null`], for every number of a series
+fn nan_if_null_in_series<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    Ok(Vec::<Option<f64>>::deserialize(deserializer)?.into_iter().map(|value| value.unwrap_or(f64::NAN)).collect())
+}
+
+impl SimulationResult {
+    /// Return the `p`-th percentile (nearest rank) of the hiding rounds of the repetitions.
+    ///
+    /// Repetitions that never hid the targets rank above all others, so this is `None` if more than
+    /// `100 - p` percent of them never did.
+    pub fn hiding_round_percentile(&self, p: f64) -> Option<usize> {
+        let mut rounds = self.hiding_rounds.clone();
+        if rounds.is_empty() {
+            return None;
+        }
+        rounds.sort_by_key(|round| round.unwrap_or(usize::MAX));
+
+        let rank = ((p / 100.0) * rounds.len() as f64).ceil() as usize;
+        rounds[rank.clamp(1, rounds.len()) - 1]
+    }
+
+    /// Return whether the success probability still increased over the last tenth of the rounds.
+    ///
+    /// This is only meaningful when the targets were never hidden in every repetition, so that all
+    /// rounds were simulated, and then suggests that more shuffles might get there.
+    pub fn is_still_improving(&self) -> bool {
+        let rounds = &self.successes_in_round;
+        let Some(&last) = rounds.last() else {
+            return false;
+        };
+        let window = (rounds.len() / 10).clamp(1, rounds.len().max(2) - 1);
+        last > rounds[rounds.len().saturating_sub(window + 1)]
+    }
+
+    /// Return the fraction of the repetitions whose targets were opened that had already hidden them.
+    ///
+    /// This is `None` if no targets were opened, either because `config.target_opening` was not set
+    /// or because the opening probability skipped every repetition.
+    pub fn safe_fraction(&self) -> Option<f64> {
+        (self.targets_opened > 0).then(|| self.safe_when_opened as f64 / self.targets_opened as f64)
+    }
+}
+
+/// Summary statistics of the hiding rounds of the individual repetitions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoundStats {
+    /// Number of repetitions that hid the target, over which the statistics are computed
+    pub count: usize,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub mean: f64,
+    /// Sample variance
+    #[serde(deserialize_with = "nan_if_null")]
+    pub variance: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub std_dev: f64,
+}
+
+impl RoundStats {
+    /// Compute the statistics over the repetitions that hid the target, or `None` if none did
+    pub fn from_rounds(hiding_rounds: &[Option<usize>]) -> Option<RoundStats> {
+        let rounds: Vec<f64> = hiding_rounds.iter().flatten().map(|&r| r as f64).collect();
+        let count = rounds.len();
+        if count == 0 {
+            return None;
+        }
+
+        let mean = rounds.iter().sum::<f64>() / count as f64;
+        let variance = if count > 1 {
+            rounds.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+
+        Some(RoundStats { count, mean, variance, std_dev: variance.sqrt() })
+    }
+}
+
+/// Return the 2.5th and 97.5th percentile of the means of `resamples` resamples (with replacement)
+/// of the hiding rounds of the repetitions that hid the target, or `None` if none did
+pub fn bootstrap_mean_interval<R: Rng>(hiding_rounds: &[Option<usize>], resamples: usize, rng: &mut R) -> Option<(f64, f64)> {
+    let rounds: Vec<usize> = hiding_rounds.iter().flatten().copied().collect();
+    if rounds.is_empty() || resamples == 0 {
+        return None;
+    }
+
+    let mut means: Vec<f64> = (0..resamples)
+        .map(|_| (0..rounds.len()).map(|_| rounds[rng.gen_range(0..rounds.len())]).sum::<usize>() as f64 / rounds.len() as f64)
+        .collect();
+    means.sort_by(f64::total_cmp);
+
+    // Nearest rank, as for the percentiles of the hiding rounds
+    let percentile = |p: f64| means[((p / 100.0) * resamples as f64).ceil().clamp(1.0, resamples as f64) as usize - 1];
+    Some((percentile(2.5), percentile(97.5)))
+}
+
+/// Return the two-sided p-value of a Mann-Whitney U (rank-sum) test that the hiding rounds `a` and `b`
+/// of two sets of repetitions come from the same distribution.
+///
+/// Repetitions that never hid the target rank above all others, and tied rounds share their average
+/// rank. The p-value uses the normal approximation with a tie correction, which is accurate from a
+/// few dozen repetitions on either side. It is 1 if either side is empty or all rounds are tied.
+pub fn rank_sum_p_value(a: &[Option<usize>], b: &[Option<usize>]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 1.0;
+    }
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let n = n_a + n_b;
+    let mut rounds: Vec<(usize, bool)> = a.iter().map(|round| (round.unwrap_or(usize::MAX), true))
+        .chain(b.iter().map(|round| (round.unwrap_or(usize::MAX), false)))
+        .collect();
+    rounds.sort_unstable();
+
+    // Sum of the ranks of `a`, and the correction of the variance for the tied groups
+    let (mut rank_sum, mut ties, mut before) = (0.0, 0.0, 0.0);
+    for group in rounds.chunk_by(|x, y| x.0 == y.0) {
+        let size = group.len() as f64;
+        let rank = before + (size + 1.0) / 2.0;
+        rank_sum += rank * group.iter().filter(|(_, in_a)| *in_a).count() as f64;
+        ties += size * size * size - size;
+        before += size;
+    }
+    let u = rank_sum - n_a * (n_a + 1.0) / 2.0;
+    let variance = n_a * n_b / 12.0 * (n + 1.0 - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+
+    // With a continuity correction, as U only takes steps of a half
+    let z = ((u - n_a * n_b / 2.0).abs() - 0.5).max(0.0) / variance.sqrt();
+    erfc(z / std::f64::consts::SQRT_2).min(1.0)
+}
+
+/// Complementary error function, with a relative error below 1.2e-7 (Numerical Recipes' `erfcc`)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806
+        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let erfc = t * (-z * z + polynomial).exp();
+    if x >= 0.0 { erfc } else { 2.0 - erfc }
+}
+
+/// Move `count` corrupted cups to the honest cups holding the most water, zeroing the water they hold
+/// and adding it to the `lost` water of every target.
+///
+/// The corrupted cups that are given up are chosen at random, so the number of corrupted cups stays
+/// the same. Target cups are only considered if `allow_target_corruption` is set.
+fn reassign_corruption<R: Rng>(water_cups: &mut [Vec<f64>], corrupted: &mut [bool], target_cups: &[usize],
+                       allow_target_corruption: bool, count: usize, lost: &mut [f64], rng: &mut R) {
+    let water_in_cup = |index: usize| water_cups.iter().map(|cups| cups[index]).sum::<f64>();
+
+    // Find the honest cups with the most water
+    let mut candidates: Vec<usize> = (0..corrupted.len())
+        .filter(|&index| !corrupted[index] && (allow_target_corruption || !target_cups.contains(&index)))
+        .collect();
+    let count = count.min(candidates.len());
+    if count == 0 {
+        return;
+    }
+    candidates.select_nth_unstable_by(count - 1, |&a, &b| water_in_cup(b).total_cmp(&water_in_cup(a)));
+
+    // Give up as many corrupted cups as we are about to corrupt
+    let currently_corrupted = (0..corrupted.len()).filter(|&index| corrupted[index]);
+    let released = currently_corrupted.choose_multiple(rng, count);
+    let count = released.len();
+    for index in released {
+        corrupted[index] = false;
+    }
+
+    for &index in &candidates[..count] {
+        corrupted[index] = true;
+        for (cups, lost) in water_cups.iter_mut().zip(lost.iter_mut()) {
+            *lost += cups[index];
+            cups[index] = 0.0;
+        }
+    }
+}
+
+/// Distribute water in uncorrupted cups of a given batch, and return the honest cups of the batch
+///
+/// `corrupted` is a mask over all cups, where `corrupted[i]` is set if cup `i` is corrupted, and
+/// every other cup of the batch is corrupted for this shuffle only with probability
+/// `transient_corruption`, see [`select_honest_batch`]. The honest cups are then shuffled by
+/// [`mix_honest_cups`] (like every shuffle of the simulation), see there for the other arguments. If
+/// the batch has no honest cups, no water moves and the returned batch is empty; it is up to the
+/// caller to report that.
+#[allow(clippy::too_many_arguments)]
+pub fn distribute_water<'a, R: Rng>(cups: &mut [f64], corrupted: &[bool], transient_corruption: f64,
+                                    shuffle_size: usize, model: ShuffleModel, rule: &dyn MixRule,
+                                    cup_capacity: f64, leak_rate: f64, summation: Summation,
+                                    orphaned: &mut f64, lost: &mut f64, batches: &'a mut BatchSelector,
+                                    rng: &mut R) -> &'a [usize] {
+    let honest_set = select_honest_batch(corrupted, transient_corruption, shuffle_size, batches, rng);
+    mix_honest_cups(cups, honest_set, model, rule, cup_capacity, leak_rate, summation, orphaned, lost, rng);
+    honest_set
+}
+
+/// Shuffle the cups in `honest_set`, the honest cups of a batch, adding the water this loses to `lost`.
+///
+/// `orphaned` is water that is not in any cup, e.g. of cups opened since the last shuffle; it is
+/// poured into the honest cups before they are mixed, see [`pour_orphaned_water`]. See
+/// [`shuffle_batch`] for `model` and `rule`, [`spill_water`] for `cup_capacity` and [`leak_water`] for
+/// `leak_rate`; the leaked water is summed with `summation`, and `rule` draws its random choices from `rng`.
+#[allow(clippy::too_many_arguments)]
+pub fn mix_honest_cups(cups: &mut [f64], honest_set: &[usize], model: ShuffleModel, rule: &dyn MixRule,
+                       cup_capacity: f64, leak_rate: f64, summation: Summation, orphaned: &mut f64, lost: &mut f64,
+                       rng: &mut dyn RngCore) {
+    pour_orphaned_water(cups, honest_set, orphaned);
+    shuffle_batch(cups, honest_set, model, rule, rng);
+    *lost += spill_water(cups, honest_set, cup_capacity);
+    if leak_rate > 0.0 {
+        *lost += leak_rate * summation.sum(honest_set.iter().map(|&index| cups[index]));
+    }
+    leak_water(cups, honest_set, leak_rate);
+}
+
+/// Clamp the water of the cups in `honest_set` to `capacity`, and return how much spilled over.
+///
+/// Like leaking, this does not conserve water on purpose, and it hides the targets early by cutting
+/// down the fullest cups. An infinite capacity never spills anything.
+pub fn spill_water(cups: &mut [f64], honest_set: &[usize], capacity: f64) -> f64 {
+    let mut spilled = 0.0;
+    for &index in honest_set {
+        if cups[index] > capacity {
+            spilled += cups[index] - capacity;
+            cups[index] = capacity;
+        }
+    }
+    spilled
+}
+
+/// Lose `leak_rate` of the water of the cups in `honest_set`, as a faulty shuffler would.
+///
+/// Unlike everything else a shuffle does, this does not conserve water: hiding gets easier simply
+/// because there is less water left to find.
+pub fn leak_water(cups: &mut [f64], honest_set: &[usize], leak_rate: f64) {
+    if leak_rate == 0.0 {
+        return;
+    }
+    for &index in honest_set {
+        cups[index] *= 1.0 - leak_rate;
+    }
+}
+
+/// Split the `orphaned` water evenly among the cups in `honest_set`, leaving none of it behind.
+///
+/// If there is no honest cup, the water stays orphaned until the next batch.
+pub fn pour_orphaned_water(cups: &mut [f64], honest_set: &[usize], orphaned: &mut f64) {
+    if *orphaned == 0.0 || honest_set.is_empty() {
+        return;
+    }
+    let share = *orphaned / honest_set.len() as f64;
+    for &index in honest_set {
+        cups[index] += share;
+    }
+    *orphaned = 0.0;
+}
+
+/// Mix the water of the cups in `honest_set` with `rule`, in the groups of `model`, drawing its random choices from `rng`
+pub fn shuffle_batch(cups: &mut [f64], honest_set: &[usize], model: ShuffleModel, rule: &dyn MixRule, rng: &mut dyn RngCore) {
+    match model {
+        ShuffleModel::FullAverage => rule.mix(cups, honest_set, rng),
+        ShuffleModel::WindowedAverage { window } => {
+            let mut sorted = honest_set.to_vec();
+            sorted.sort_unstable();
+            for group in sorted.chunk_by(|a, b| a / window == b / window) {
+                rule.mix(cups, group, rng);
+            }
+        }
+    }
+}
+
+/// Pick the next batch of at most `shuffle_size` distinct cups from `batches` and return the honest ones among them.
+///
+/// Besides the cups masked by `corrupted`, every cup of the batch is corrupted with probability
+/// `transient_corruption` for this shuffle only: it keeps its water and is left out of the mix, but
+/// may be honest again in the next shuffle.
+pub fn select_honest_batch<'a, R: Rng>(corrupted: &[bool], transient_corruption: f64, shuffle_size: usize,
+                                       batches: &'a mut BatchSelector, rng: &mut R) -> &'a [usize] {
+    batches.fill_batch(corrupted.len(), shuffle_size, rng);
+    honest_cups_of_batch(corrupted, transient_corruption, batches, rng)
+}
+
+/// Return the honest cups of the current batch of `batches`, see [`select_honest_batch`]
+fn honest_cups_of_batch<'a, R: Rng>(corrupted: &[bool], transient_corruption: f64, batches: &'a mut BatchSelector, rng: &mut R) -> &'a [usize] {
+    // Get set of honest indices that will get shuffled
+    let BatchSelector { batch, honest, .. } = batches;
+    honest.clear();
+    honest.extend(batch.iter().copied().filter(|&index| !corrupted[index]));
+    // Without transient corruption, no random numbers are drawn, so that fixed-seed results are unchanged
+    if transient_corruption > 0.0 {
+        honest.retain(|_| !rng.gen_bool(transient_corruption));
+    }
+    honest
+}
+
+/// Source of the shuffle batches of a repetition, following a [`BatchScheduler`]
+///
+/// The batches are kept in buffers that are reused from one shuffle to the next, so that shuffling
+/// does not allocate.
+#[derive(Clone, Debug)]
+pub struct BatchSelector {
+    scheduler: BatchScheduler,
+    weights: SelectionWeights,
+    /// Order of the cups in the current epoch of disjoint batches
+    permutation: Vec<usize>,
+    /// Start of the next batch in `permutation`
+    position: usize,
+    /// The current batch
+    batch: Vec<usize>,
+    /// The honest cups of the current batch
+    honest: Vec<usize>,
+}
+
+impl BatchSelector {
+    /// Create a selector; `weights` only apply to [`BatchScheduler::RandomIid`]
+    pub fn new(scheduler: BatchScheduler, weights: SelectionWeights) -> BatchSelector {
+        BatchSelector { scheduler, weights, permutation: Vec::new(), position: 0, batch: Vec::new(), honest: Vec::new() }
+    }
+
+    /// Return the next batch of `shuffle_size` distinct cups out of `vector_length`.
+    ///
+    /// Disjoint batches start a new epoch with a fresh permutation once every cup was in a batch;
+    /// the last batch of an epoch is smaller if `shuffle_size` does not divide `vector_length`.
+    pub fn next_batch<R: Rng>(&mut self, vector_length: usize, shuffle_size: usize, rng: &mut R) -> &[usize] {
+        self.fill_batch(vector_length, shuffle_size, rng);
+        &self.batch
+    }
+
+    /// Replace `self.batch` with the next batch
+    fn fill_batch<R: Rng>(&mut self, vector_length: usize, shuffle_size: usize, rng: &mut R) {
+        match self.scheduler {
+            BatchScheduler::RandomIid => match self.weights {
+                // Picks the indices directly, drawing a number per chosen cup rather than per cup of the vector
+                SelectionWeights::Uniform => {
+                    self.batch.clear();
+                    self.batch.extend(index::sample(rng, vector_length, shuffle_size.min(vector_length)));
+                }
+                weights => {
+                    let sample = index::sample_weighted(rng, vector_length, |index| weights.weight(vector_length, index), shuffle_size)
+                        .expect("selection weights are finite and non-negative");
+                    self.batch.clear();
+                    self.batch.extend(sample.iter());
+                }
+            },
+            BatchScheduler::RoundRobinDisjoint => {
+                if self.permutation.len() != vector_length {
+                    self.permutation = (0..vector_length).collect();
+                    self.position = vector_length;
+                }
+                if self.position >= vector_length {
+                    self.permutation.shuffle(rng);
+                    self.position = 0;
+                }
+                let end = usize::min(self.position + shuffle_size, vector_length);
+                self.batch.clear();
+                self.batch.extend_from_slice(&self.permutation[self.position..end]);
+                self.position = end;
+            }
+        }
+    }
+}
+
+/// Distribute the water of the cups in `honest_set` among them.
+///
+/// Each cup moves `mixing_factor` of the way towards the average: `1.0` is a perfect shuffle that
+/// leaves every cup with exactly the average, smaller values model shuffles that only partially mix.
+/// Either way the total water of the batch is conserved, up to the rounding of its sum with `summation`.
+pub fn mix_water(cups: &mut [f64], honest_set: &[usize], mixing_factor: f64, summation: Summation) {
+    let num_honest = honest_set.len();
+    if num_honest == 0 {
+        return;
+    }
+
+    // Find out how much total water we are distributing
+    let total_water = summation.sum(honest_set.iter().map(|&index| cups[index]));
+    let avg_water = total_water / (num_honest as f64);
+    // Non-finite water that was already in the batch is reported by the simulation, but averaging must not create it
+    debug_assert!(avg_water.is_finite() || honest_set.iter().any(|&index| !cups[index].is_finite()),
+                  "averaging finite water gave {avg_water}");
+
+    // Pour water to all the cups (a factor of 1 results in exactly `avg_water`)
+    for &index in honest_set {
+        cups[index] = (1.0 - mixing_factor) * cups[index] + mixing_factor * avg_water;
+    }
+}
+
+/// Shannon entropy (in bits) of the water distribution over `cups`, after normalizing it to 1.
+///
+/// This is `log2(n)` when the water is spread evenly over all `n` cups, and 0 when a single cup
+/// holds all of it, so higher is better hidden.
+pub fn water_entropy(cups: &[f64]) -> f64 {
+    let total: f64 = cups.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    -cups.iter().filter(|&&water| water > 0.0).map(|water| water / total).map(|p| p * p.log2()).sum::<f64>()
+}
+
+/// Gini coefficient of the water distribution over `cups`.
+///
+/// This is 0 when every cup holds the same amount, and approaches 1 when a single cup holds all of
+/// it, so lower is better hidden.
+pub fn water_gini(cups: &[f64]) -> f64 {
+    let total: f64 = cups.iter().sum();
+    if cups.is_empty() || total <= 0.0 {
+        return 0.0;
+    }
+    let mut sorted = cups.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let n = sorted.len() as f64;
+    let weighted: f64 = sorted.iter().enumerate().map(|(i, water)| (i + 1) as f64 * water).sum();
+    2.0 * weighted / (n * total) - (n + 1.0) / n
+}
+
+/// Return the first round where we managed to hide the cup in at least `success_probability` of the
+/// repetitions (in all of them for `1.0`), or `None` if we never did.
+///
+/// Rounds are 1-indexed: round `r` is the state after `r` shuffles, so the earliest possible
+/// success is round 1. Entry `t` of `sum_succ_in_round` is the number of successful repetitions in
+/// round `t+1`.
+pub fn get_success_round(sum_succ_in_round: &[u64], repetitions: usize, success_probability: f64) -> Option<usize> {
+    trace!("success probability after rounds:");
+    for (t, round_success) in sum_succ_in_round.iter().enumerate() {
+        // Success count of previous round (should not underflow if t==0)
+        let prev_round_success = &sum_succ_in_round[t.saturating_sub(1)];
+
+        // Probability that shuffling completes in each round, only where it changes
+        if t == 0 || t == sum_succ_in_round.len() - 1 || round_success != prev_round_success {
+            trace!("\t{} \t {}", t+1, *round_success as f64 / repetitions as f64);
+        }
+
+        if reaches_probability(*round_success, repetitions, success_probability) {
+            return Some(t+1);
+        }
+    }
+
+    None
+}
+
+/// Probability levels whose success rounds [`run_sim`] reports in `SimulationResult::probability_rounds`
+pub const PROBABILITY_LEVELS: [f64; 4] = [0.5, 0.9, 0.99, 1.0];
+
+/// Return the first round where we managed to hide the cup in at least each of `levels` of the
+/// repetitions, as [`get_success_round`] does for a single level, or `None` for levels we never reached.
+///
+/// Together, the rounds show how the hiding converges, e.g. that most repetitions hide the cup
+/// quickly while the last few take much longer.
+pub fn get_success_rounds(sum_succ_in_round: &[u64], repetitions: usize, levels: &[f64]) -> Vec<Option<usize>> {
+    levels.iter()
+        .map(|&level| {
+            sum_succ_in_round.iter()
+                .position(|&round_success| reaches_probability(round_success, repetitions, level))
+                .map(|t| t+1)
+        })
+        .collect()
+}
+
+/// Return whether `successes` of `repetitions` is a success probability of at least `probability`
+fn reaches_probability(successes: u64, repetitions: usize, probability: f64) -> bool {
+    // Divide rather than multiply: the quotient is correctly rounded, so e.g. 7 of 100 repetitions
+    // compare equal to a probability of 0.07, and only all repetitions reach 1.0
+    successes as f64 / repetitions as f64 >= probability
+}
+
+/// Everything about a repetition in the middle of its rounds that the next rounds depend on, except for its
+/// generator, saved by [`SimulationState::snapshot`] to continue it with [`SimulationState::from_snapshot`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Corruption threshold of the repetition
+    pub fraction_corrupted: f64,
+    /// Index of the repetition among those of its threshold
+    pub repetition: usize,
+    /// Corruption budget of the adversary
+    pub corrupted_commitments: usize,
+    /// Water levels below which the targets count as hidden, the primary one first
+    pub target_eps: Vec<f64>,
+    /// Number of rounds simulated so far
+    pub round: usize,
+    /// Water of every target, indexed by cup
+    pub water_cups: Vec<Vec<f64>>,
+    /// Cups that are currently corrupted, in increasing order
+    pub corrupted_cups: Vec<usize>,
+    /// First round of the current streak of rounds in which the targets are hidden, for every target level
+    pub hidden_since: Vec<Option<usize>>,
+    /// Number of cups above every target level after the last round
+    pub cups_above_eps: Vec<u64>,
+    /// Number of rounds so far in which the targets went from not hidden to hidden or back, for every target level
+    pub crossings: Vec<u64>,
+    /// Water of every target taken from opened cups, which the next batch redistributes
+    pub orphaned: Vec<f64>,
+    /// Number of honest cups mixed by all shuffles so far
+    pub honest_shuffled: usize,
+    /// Whether the targets were hidden when they were opened, if they were
+    pub hidden_when_opened: Option<Vec<bool>>,
+    /// Water of every target at the start
+    pub initial_water: Vec<f64>,
+    /// Water of every target that left the simulation
+    pub lost: Vec<f64>,
+    /// Largest drift of the tracked water so far
+    pub max_mass_drift: f64,
+}
+
+/// State of a single repetition of [`run_sim`], which is advanced one round at a time.
+///
+/// This is what `run_sim` runs in lockstep for all repetitions, and it can also be driven directly,
+/// e.g. to step through a repetition or to visualize how the water spreads. All random choices of the
+/// repetition are drawn from an `R`, which is a [`StdRng`] unless created with [`SimulationState::with_rng`].
+pub struct SimulationState<'a, R = StdRng> {
+    config: &'a SimulationConfig,
+    fraction_corrupted_commitments: f64,
+    repetition: usize,
+    corrupted_commitments: usize,
+    /// Water levels below which the targets count as hidden; the first one is the primary level, and
+    /// every other one is tracked alongside it, as the water does not depend on them
+    target_eps: Vec<f64>,
+    /// Number of rounds simulated so far
+    round: usize,
+    rng: R,
+    /// Water of every target, indexed by cup
+    water_cups: Vec<Vec<f64>>,
+    /// Mask of the corrupted cups
+    bad_commitment_indices: Vec<bool>,
+    /// First round of the current streak of rounds in which the targets are hidden, for every target level
+    hidden_since: Vec<Option<usize>>,
+    /// Number of cups above every target level after the last round, counted once per target
+    cups_above_eps: Vec<u64>,
+    /// Number of rounds in which the targets went from not hidden to hidden or back, for every target level
+    crossings: Vec<u64>,
+    /// Water of every target taken from opened cups, which the next batch redistributes
+    orphaned: Vec<f64>,
+    /// Number of honest cups mixed by all shuffles so far
+    honest_shuffled: usize,
+    /// Whether the targets were hidden (under every target level) when they were opened, if they were
+    hidden_when_opened: Option<Vec<bool>>,
+    /// Water of every target at the start, before any of it was zeroed
+    initial_water: Vec<f64>,
+    /// Water of every target that was zeroed in corrupted cups or leaked, and so left the simulation
+    lost: Vec<f64>,
+    /// Largest difference between the tracked water of any target after a round and its initial water
+    max_mass_drift: f64,
+    batches: BatchSelector,
+    /// Generator of the batches shared by all repetitions, with `config.fixed_schedule`
+    schedule: Option<R>,
+}
+
+impl<'a> SimulationState<'a> {
+    /// Set up repetition `repetition` of `run_sim(config, fraction_corrupted_commitments)`, before its first round
+    pub fn new(config: &'a SimulationConfig, fraction_corrupted_commitments: f64, repetition: usize) -> Result<Self, SimError> {
+        SimulationState::with_rng(config, fraction_corrupted_commitments, repetition)
+    }
+
+    /// Continue a repetition from a [`StateSnapshot`] of it, taken with the same `config`.
+    ///
+    /// A generator cannot be saved, so the resumed rounds draw their random choices from a stream of
+    /// their own, which only depends on `config.seed`, the repetition and the round of the snapshot:
+    /// resuming the same snapshot always continues the same way, but not necessarily as the saved
+    /// repetition went on. Disjoint batches start a new epoch.
+    pub fn from_snapshot(config: &'a SimulationConfig, snapshot: &StateSnapshot) -> Result<Self, SimError> {
+        SimulationState::from_snapshot_with_rng(config, snapshot)
+    }
+}
+
+impl<'a, R: Rng + SeedableRng> SimulationState<'a, R> {
+    /// Like [`SimulationState::new`], with an `R` seeded like the RNG of repetition `repetition` of
+    /// `run_sim_with_rng::<R>(config, fraction_corrupted_commitments)`
+    pub fn with_rng(config: &'a SimulationConfig, fraction_corrupted_commitments: f64, repetition: usize) -> Result<Self, SimError> {
+        let (corrupted_commitments, target_eps) = threshold_parameters(config, fraction_corrupted_commitments)?;
+        Ok(SimulationState::with_parameters(config, fraction_corrupted_commitments, corrupted_commitments, vec![target_eps], repetition))
+    }
+
+    /// Like [`SimulationState::new`], for parameters that were already validated by [`threshold_parameters`]
+    fn with_parameters(config: &'a SimulationConfig, fraction_corrupted_commitments: f64, corrupted_commitments: usize, target_eps: Vec<f64>,
+                       repetition: usize) -> Self {
+        let SimulationConfig { vector_length, ref target_cups, ref initial_distribution, .. } = *config;
+        let mut rng: R = derived_rng(config.seed, repetition);
+
+        // Cups that hold water of a custom distribution must not start out corrupted either
+        let is_protected = |index: &usize| match initial_distribution {
+            InitialDistribution::Custom(water) => target_cups.contains(index) || water.iter().any(|(cup, _)| cup == index),
+            _ => target_cups.contains(index),
+        };
+
+        // The count is drawn from its own RNG, so that a repetition that draws the nominal count is the
+        // same as with a fixed count
+        let corrupted_commitments = if config.corruption_spread == CorruptionSpread::Fixed {
+            corrupted_commitments
+        } else {
+            let candidates = (0..vector_length).filter(|index| !is_protected(index)).count();
+            let mut count_rng: R = derived_rng(!config.seed, repetition);
+            config.corruption_spread.sample(corrupted_commitments, candidates, &mut count_rng)
+        };
+
+        // Select random subset of commitments to be corrupt (do not corrupt the targets); cups that are
+        // opened progressively or chased after every round are only corrupted once the rounds start, and
+        // transient corruption never lasts beyond a shuffle
+        let mut bad_commitment_indices = vec![false; vector_length];
+        if let Some(corrupted_cups) = &config.corrupted_cups {
+            for &index in corrupted_cups {
+                bad_commitment_indices[index] = true;
+            }
+        } else if !matches!(config.adversary, AdversaryStrategy::ProgressiveOpening { .. } | AdversaryStrategy::CorruptMaxEachRound
+                                              | AdversaryStrategy::Transient) {
+            let candidates = (0..vector_length).filter(|index| !is_protected(index));
+            for index in candidates.choose_multiple(&mut rng, corrupted_commitments) {
+                bad_commitment_indices[index] = true;
+            }
+        }
+
+        // Initially all cups have 0 water apart for the cup we care about tracking; every target has its own water
+        let mut water_cups: Vec<Vec<f64>> = target_cups.iter().map(|&target_cup_indx| {
+            let mut cups = vec![0.0; vector_length];
+            match initial_distribution {
+                InitialDistribution::SinglePoint => cups[target_cup_indx] = 1.0,
+                InitialDistribution::Uniform(count) => {
+                    let honest = (0..vector_length).filter(|&index| !bad_commitment_indices[index]);
+                    let chosen = honest.choose_multiple(&mut rng, *count);
+                    let water = 1.0 / chosen.len() as f64;
+                    for index in chosen {
+                        cups[index] = water;
+                    }
+                }
+                InitialDistribution::Custom(water) => {
+                    for &(index, amount) in water {
+                        cups[index] += amount;
+                    }
+                }
+            }
+            cups
+        }).collect();
+
+        // A fixed corruption may include cups with water, which the adversary then sees through
+        let initial_water: Vec<f64> = water_cups.iter().map(|cups| config.summation.sum(cups.iter().copied())).collect();
+        let mut lost = vec![0.0; target_cups.len()];
+        for (cups, lost) in water_cups.iter_mut().zip(lost.iter_mut()) {
+            for (water, _) in cups.iter_mut().zip(&bad_commitment_indices).filter(|(_, &is_bad)| is_bad) {
+                *lost += *water;
+                *water = 0.0;
+            }
+        }
+
+        let batches = BatchSelector::new(config.batch_scheduler, config.selection_weights);
+        let orphaned = vec![0.0; target_cups.len()];
+        let levels = target_eps.len();
+        SimulationState { config, fraction_corrupted_commitments, repetition, corrupted_commitments, target_eps, round: 0, rng, water_cups,
+                          bad_commitment_indices,
+                          hidden_since: vec![None; levels], cups_above_eps: vec![0; levels], crossings: vec![0; levels], orphaned, honest_shuffled: 0,
+                          hidden_when_opened: None, initial_water, lost, max_mass_drift: 0.0, batches,
+                          schedule: config.fixed_schedule.then(|| derived_rng(config.seed, FIXED_SCHEDULE_STREAM)) }
+    }
+
+    /// Like [`SimulationState::from_snapshot`], drawing the random choices of the resumed rounds from an `R`
+    pub fn from_snapshot_with_rng(config: &'a SimulationConfig, snapshot: &StateSnapshot) -> Result<Self, SimError> {
+        let StateSnapshot { fraction_corrupted, repetition, corrupted_commitments, ref target_eps, round, ref water_cups, ref corrupted_cups,
+                            ref hidden_since, ref cups_above_eps, ref crossings, ref orphaned, honest_shuffled, ref hidden_when_opened, ref initial_water,
+                            ref lost, max_mass_drift } = *snapshot;
+        config.validate()?;
+        let (vector_length, targets) = (config.vector_length, config.target_cups.len());
+        let levels = target_eps.len();
+        let consistent = !target_eps.is_empty() && water_cups.len() == targets && water_cups.iter().all(|cups| cups.len() == vector_length)
+            && [orphaned.len(), initial_water.len(), lost.len()].iter().all(|&len| len == targets)
+            && hidden_since.len() == levels && cups_above_eps.len() == levels && crossings.len() == levels
+            && hidden_when_opened.as_ref().is_none_or(|hidden| hidden.len() == levels);
+        if !consistent {
+            return Err(SimError::SnapshotMismatch { vector_length, targets });
+        }
+        let mut bad_commitment_indices = vec![false; vector_length];
+        for &cup in corrupted_cups {
+            *bad_commitment_indices.get_mut(cup).ok_or(SimError::CupOutOfRange { cup, vector_length })? = true;
+        }
+
+        let resume_seed = seed_for(config.seed, round);
+        let resume_seed = u64::from_le_bytes(resume_seed[..8].try_into().expect("a seed has 32 bytes"));
+        let rng = derived_rng(resume_seed, repetition);
+        Ok(SimulationState { config, fraction_corrupted_commitments: fraction_corrupted, repetition, corrupted_commitments,
+                             target_eps: target_eps.clone(), round, rng, water_cups: water_cups.clone(), bad_commitment_indices,
+                             hidden_since: hidden_since.clone(), cups_above_eps: cups_above_eps.clone(), crossings: crossings.clone(),
+                             orphaned: orphaned.clone(),
+                             honest_shuffled, hidden_when_opened: hidden_when_opened.clone(), initial_water: initial_water.clone(),
+                             lost: lost.clone(), max_mass_drift,
+                             batches: BatchSelector::new(config.batch_scheduler, config.selection_weights),
+                             schedule: config.fixed_schedule.then(|| derived_rng(resume_seed, FIXED_SCHEDULE_STREAM)) })
+    }
+
+    /// Save everything about the repetition that the next rounds depend on, except for its generator
+    /// and batch schedule, see [`SimulationState::from_snapshot`]
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            fraction_corrupted: self.fraction_corrupted_commitments,
+            repetition: self.repetition,
+            corrupted_commitments: self.corrupted_commitments,
+            target_eps: self.target_eps.clone(),
+            round: self.round,
+            water_cups: self.water_cups.clone(),
+            corrupted_cups: (0..self.bad_commitment_indices.len()).filter(|&index| self.bad_commitment_indices[index]).collect(),
+            hidden_since: self.hidden_since.clone(),
+            cups_above_eps: self.cups_above_eps.clone(),
+            crossings: self.crossings.clone(),
+            orphaned: self.orphaned.clone(),
+            honest_shuffled: self.honest_shuffled,
+            hidden_when_opened: self.hidden_when_opened.clone(),
+            initial_water: self.initial_water.clone(),
+            lost: self.lost.clone(),
+            max_mass_drift: self.max_mass_drift,
+        }
+    }
+
+    /// Simulate rounds until the targets are hidden (but at least one) or `config.max_shuffles` rounds
+    /// were simulated, and return the highest water level of any cup after every one of them
+    pub fn trace(&mut self) -> Result<Vec<f64>, SimError> {
+        let mut max_water_in_round = Vec::new();
+        while self.round < self.config.max_shuffles {
+            let max_water = self.step();
+            if !max_water.is_finite() {
+                return Err(SimError::NonFiniteWater { round: self.round });
+            }
+            max_water_in_round.push(max_water);
+            if self.hidden_since().is_some() {
+                break;
+            }
+        }
+        Ok(max_water_in_round)
+    }
+
+    /// Number of rounds simulated so far
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// Water of every target (in the order of `config.target_cups`), indexed by cup
+    pub fn water(&self) -> &[Vec<f64>] {
+        &self.water_cups
+    }
+
+    /// Mask of the cups that are currently corrupted
+    pub fn corrupted(&self) -> &[bool] {
+        &self.bad_commitment_indices
+    }
+
+    /// Water level below which the targets count as hidden
+    pub fn target_eps(&self) -> f64 {
+        self.target_eps[0]
+    }
+
+    /// First round of the current streak of rounds in which the targets are hidden, if they are now
+    pub fn hidden_since(&self) -> Option<usize> {
+        self.hidden_since[0]
+    }
+
+    /// Simulate the next round and return the highest water level of any cup afterwards
+    pub fn step(&mut self) -> f64 {
+        self.run_round(None).max_water
+    }
+
+    /// Mean entropy and Gini coefficient of the water of every target over the honest cups
+    fn concentration(&self) -> (f64, f64) {
+        let (entropy, gini) = self.water_cups.iter()
+            .map(|cups| {
+                let honest: Vec<f64> = cups.iter().zip(&self.bad_commitment_indices)
+                    .filter(|(_, &is_bad)| !is_bad)
+                    .map(|(&water, _)| water)
+                    .collect();
+                (water_entropy(&honest), water_gini(&honest))
+            })
+            .fold((0.0, 0.0), |(entropy, gini), (e, g)| (entropy + e, gini + g));
+        let targets = self.water_cups.len() as f64;
+        (entropy / targets, gini / targets)
+    }
+
+    /// What this repetition contributes to the result of target level `level`, as of the rounds simulated so far
+    fn outcome(&self, level: usize) -> RepetitionOutcome {
+        let (entropy, gini) = self.concentration();
+        RepetitionOutcome { hiding_round: self.hidden_since[level], crossings: self.crossings[level], honest_shuffled: self.honest_shuffled,
+                            entropy, gini, max_mass_drift: self.max_mass_drift,
+                            hidden_when_opened: self.hidden_when_opened.as_ref().map(|hidden| hidden[level]) }
+    }
+
+    /// Open up to `rate` random honest cups other than the targets, without exceeding `budget` opened cups.
+    /// With `redistribute`, their water is orphaned rather than lost.
+    fn open_cups(&mut self, target_cups: &[usize], rate: usize, budget: usize, redistribute: bool) {
+        let opened = self.bad_commitment_indices.iter().filter(|&&is_bad| is_bad).count();
+        let count = rate.min(budget.saturating_sub(opened));
+        if count == 0 {
+            return;
+        }
+
+        let bad_commitment_indices = &self.bad_commitment_indices;
+        let candidates = (0..bad_commitment_indices.len())
+            .filter(|&index| !bad_commitment_indices[index] && !target_cups.contains(&index));
+
+        for index in candidates.choose_multiple(&mut self.rng, count) {
+            self.bad_commitment_indices[index] = true;
+            for ((cups, orphaned), lost) in self.water_cups.iter_mut().zip(self.orphaned.iter_mut()).zip(self.lost.iter_mut()) {
+                if redistribute {
+                    *orphaned += cups[index];
+                } else {
+                    *lost += cups[index];
+                }
+                cups[index] = 0.0;
+            }
+        }
+    }
+
+    /// Corrupt the honest cup holding the most water and zero it, unless `budget` cups are corrupted already
+    fn corrupt_fullest_cup(&mut self, target_cups: &[usize], allow_target_corruption: bool, budget: usize) {
+        let corrupted = self.bad_commitment_indices.iter().filter(|&&is_bad| is_bad).count();
+        if corrupted >= budget {
+            return;
+        }
+
+        let water_cups = &self.water_cups;
+        let water_in_cup = |index: usize| water_cups.iter().map(|cups| cups[index]).sum::<f64>();
+        let fullest = (0..self.bad_commitment_indices.len())
+            .filter(|&index| !self.bad_commitment_indices[index] && (allow_target_corruption || !target_cups.contains(&index)))
+            .max_by(|&a, &b| water_in_cup(a).total_cmp(&water_in_cup(b)));
+        if let Some(index) = fullest {
+            self.bad_commitment_indices[index] = true;
+            for (cups, lost) in self.water_cups.iter_mut().zip(self.lost.iter_mut()) {
+                *lost += cups[index];
+                cups[index] = 0.0;
+            }
+        }
+    }
+
+    /// Do the shuffles of the next round and return how well the targets are hidden afterwards, calling `after_shuffle`
+    /// with the (1-indexed) shuffle and the highest water level of any cup after every shuffle, if there is one
+    fn run_round(&mut self, mut after_shuffle: Option<&mut dyn FnMut(usize, f64)>) -> RoundOutcome {
+        let SimulationConfig { shuffle_size, ref target_cups, adversary, allow_target_corruption,
+                               mixing_factor, leak_rate, cup_capacity, shuffle_model, mix_rule, summation, shuffles_per_round, success_criterion, .. } = *self.config;
+        let (corrupted_commitments, t) = (self.corrupted_commitments, self.round);
+        self.round += 1;
+        let transient_corruption = match adversary {
+            AdversaryStrategy::Transient => corrupted_commitments as f64 / self.bad_commitment_indices.len() as f64,
+            _ => 0.0,
+        };
+
+        // A progressive adversary opens more cups, until it runs out of budget
+        if let AdversaryStrategy::ProgressiveOpening { rate, redistribute } = adversary {
+            self.open_cups(target_cups, rate, corrupted_commitments, redistribute);
+        }
+
+        // Averaging never pushes a cup above the fullest cup, so neither the target cups nor any other cup can end the
+        // round with more water than the fullest cup held before it, unless orphaned water is poured in. The target
+        // cups themselves can gain water, whenever a batch mixes them with a fuller cup.
+        let fullest_before: Vec<f64> = if cfg!(debug_assertions) && self.orphaned.iter().all(|&orphaned| orphaned == 0.0) {
+            self.water_cups.iter().map(|cups| max_water(cups)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mean = MeanMix { mixing_factor, summation };
+        let rule: &dyn MixRule = match mix_rule {
+            MixRuleKind::Mean => &mean,
+            MixRuleKind::PairwiseSwap => &PairwiseSwapMix,
+        };
+
+        // Each shuffler distributes the water to all the cups, one after the other within a round
+        for shuffle in 1..=shuffles_per_round {
+            let vector_length = self.bad_commitment_indices.len();
+            let honest_set = match &mut self.schedule {
+                // Only the batch comes from the shared schedule, which cups of it are honest is up to the repetition
+                Some(schedule) => {
+                    let size = self.config.shuffle_size_dist.sample(shuffle_size, vector_length, schedule);
+                    self.batches.fill_batch(vector_length, size, schedule);
+                    honest_cups_of_batch(&self.bad_commitment_indices, transient_corruption, &mut self.batches, &mut self.rng)
+                }
+                None => {
+                    let size = self.config.shuffle_size_dist.sample(shuffle_size, vector_length, &mut self.rng);
+                    select_honest_batch(&self.bad_commitment_indices, transient_corruption, size, &mut self.batches, &mut self.rng)
+                }
+            };
+            if honest_set.is_empty() {
+                warn!("no honest commitment selected!");
+            }
+            self.honest_shuffled += honest_set.len();
+            for ((cups, orphaned), lost) in self.water_cups.iter_mut().zip(self.orphaned.iter_mut()).zip(self.lost.iter_mut()) {
+                mix_honest_cups(cups, honest_set, shuffle_model, rule, cup_capacity.unwrap_or(f64::INFINITY), leak_rate, summation, orphaned, lost,
+                                &mut self.rng);
+            }
+            if let Some(after_shuffle) = after_shuffle.as_mut() {
+                after_shuffle(shuffle, self.water_cups.iter().map(|cups| max_water(cups)).fold(0.0, nan_max));
+            }
+        }
+        for (cups, &before) in self.water_cups.iter().zip(&fullest_before).filter(|(_, before)| before.is_finite()) {
+            let after = max_water(cups);
+            debug_assert!(after.is_nan() || after - before <= 1e-9 * before.abs().max(1.0),
+                          "the fullest cup gained water in round {}, from {before} to {after}", self.round);
+        }
+
+        // An adaptive adversary periodically chases the water
+        if let AdversaryStrategy::AdaptiveGreedy { interval, portion } = adversary {
+            if (t+1).is_multiple_of(interval) {
+                let count = (portion * corrupted_commitments as f64).round() as usize;
+                reassign_corruption(&mut self.water_cups, &mut self.bad_commitment_indices, target_cups,
+                                    allow_target_corruption, count, &mut self.lost, &mut self.rng);
+            }
+        }
+        if adversary == AdversaryStrategy::CorruptMaxEachRound {
+            self.corrupt_fullest_cup(target_cups, allow_target_corruption, corrupted_commitments);
+        }
+
+        // Sanity check: no water was placed in bad, i.e. corupted or opened, cups
+        for cups in self.water_cups.iter() {
+            for (water, &is_bad) in cups.iter().zip(self.bad_commitment_indices.iter()) {
+                if is_bad {
+                    assert_eq!(*water, 0.0);
+                }
+            }
+        }
+
+        // Check whether all target commitments are hidden sufficiently well, under every target level, once the burn-in is over
+        let burnt_in = t + 1 > self.config.burn_in;
+        let max_water = self.water_cups.iter().map(|cups| max_water(cups)).fold(0.0, nan_max);
+        let secret_difference = self.secret_difference();
+        for (level, &target_eps) in self.target_eps.iter().enumerate() {
+            // The tolerance only forgives rounding residue, the reported target level stays as it is
+            let level_with_tolerance = target_eps + self.config.eps_tolerance;
+            // Targets that were opened before they were hidden are found, however the water spreads afterwards
+            let found = self.hidden_when_opened.as_ref().is_some_and(|hidden| !hidden[level]);
+            let hidden = burnt_in && match success_criterion {
+                SuccessCriterion::Hidden => max_water < level_with_tolerance,
+                SuccessCriterion::Unlinkable => secret_difference < level_with_tolerance,
+            } && !found;
+            if hidden != self.hidden_since[level].is_some() {
+                self.crossings[level] += 1;
+            }
+            if hidden {
+                self.hidden_since[level].get_or_insert(t+1);
+            } else {
+                self.hidden_since[level] = None;
+            }
+            self.cups_above_eps[level] = self.water_cups.iter().flatten().filter(|&&water| water > level_with_tolerance).count() as u64;
+        }
+
+        // Opening the targets reveals whether they were hidden in this round, and corrupts them from then on
+        // (zeroing their water, like any opened cup)
+        if let Some(TargetOpening { round, probability }) = self.config.target_opening {
+            if round == t+1 && self.rng.gen_bool(probability) {
+                self.hidden_when_opened = Some(self.hidden_since.iter().map(Option::is_some).collect());
+                for &cup in target_cups {
+                    self.bad_commitment_indices[cup] = true;
+                    for (cups, lost) in self.water_cups.iter_mut().zip(self.lost.iter_mut()) {
+                        *lost += cups[cup];
+                        cups[cup] = 0.0;
+                    }
+                }
+            }
+        }
+
+        // Shuffles only move water between honest cups, so with what was zeroed, leaked or orphaned it adds up to the
+        // initial water. NaN water is reported as an error by the caller instead.
+        if self.config.track_mass || cfg!(debug_assertions) {
+            for (drift, initial) in self.mass_drift().into_iter().zip(&self.initial_water) {
+                debug_assert!(drift.is_nan() || drift <= 1e-9 * initial.abs().max(1.0),
+                              "tracked water drifted by {drift} from the initial {initial} in round {}", self.round);
+                self.max_mass_drift = nan_max(self.max_mass_drift, drift);
+            }
+        }
+        RoundOutcome { max_water, secret_difference }
+    }
+
+    /// Difference between the tracked water of every target (in the cups and orphaned, or zeroed or
+    /// leaked on the way) and its initial water, which is 0 up to rounding
+    fn mass_drift(&self) -> Vec<f64> {
+        self.water_cups.iter().zip(&self.orphaned).zip(&self.lost).zip(&self.initial_water)
+            .map(|(((cups, orphaned), lost), initial)| (self.config.summation.sum(cups.iter().copied()) + orphaned + lost - initial).abs())
+            .collect()
+    }
+
+    /// Largest difference between the water of any two targets in any cup, which is 0 if there is
+    /// only one target
+    fn secret_difference(&self) -> f64 {
+        if self.water_cups.len() < 2 {
+            return 0.0;
+        }
+        (0..self.bad_commitment_indices.len())
+            .map(|index| {
+                let (min, max) = self.water_cups.iter()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), cups| (min.min(cups[index]), nan_max(max, cups[index])));
+                max - min
+            })
+            .fold(0.0, nan_max)
+    }
+}
+
+/// How well the targets of a repetition are hidden after a round; whether they count as hidden under
+/// each target level is kept in the [`SimulationState`]
+struct RoundOutcome {
+    /// Highest water level of any cup
+    max_water: f64,
+    /// See [`SimulationState::secret_difference`]
+    secret_difference: f64,
+}
+
+/// What a repetition contributes to the result of a target level, taken in the round the level is done
+#[derive(Clone, Copy, Debug)]
+struct RepetitionOutcome {
+    /// First round of the streak of hidden rounds that lasted until then, if the targets were hidden
+    hiding_round: Option<usize>,
+    crossings: u64,
+    honest_shuffled: usize,
+    /// See [`SimulationState::concentration`]
+    entropy: f64,
+    gini: f64,
+    max_mass_drift: f64,
+    /// Whether the targets were hidden when they were opened, if they were
+    hidden_when_opened: Option<bool>,
+}
+
+/// The larger of two water levels, or NaN if either is NaN (unlike `f64::max`, which would hide it)
+fn nan_max(a: f64, b: f64) -> f64 {
+    if b > a || b.is_nan() { b } else { a }
+}
+
+/// Return the highest water level of any of the `cups`, or 0 if there are none.
+///
+/// Only the level matters for the hiding decision, not which cup holds it, so ties need no
+/// tie-break: the result is the same for any order of the cups, which keeps it deterministic if
+/// they are ever stored or visited in a different order. A NaN anywhere makes the result NaN.
+pub fn max_water(cups: &[f64]) -> f64 {
+    cups.iter().copied().fold(0.0, nan_max)
+}
+
+/// Derive the seed of the `index`-th of many parallel RNGs, e.g. of the repetitions of a threshold,
+/// from their common `base` seed.
+///
+/// The seed is drawn from stream `index` of a ChaCha20 generator keyed with `base`. Distinct streams
+/// of the same key are independent for all practical purposes, so unlike seeds such as `base + index`
+/// (which differ in a few low bits), the derived seeds give uncorrelated generators for any `base`,
+/// while every pair of arguments always gives the same seed.
+pub fn seed_for(base: u64, index: usize) -> [u8; 32] {
+    let mut stream = ChaCha20Rng::seed_from_u64(base);
+    stream.set_stream(index as u64);
+    let mut seed = [0; 32];
+    stream.fill_bytes(&mut seed);
+    seed
+}
+
+/// Index of the generator derived from `config.seed` that draws the batches (and their sizes) of every repetition
+/// with `config.fixed_schedule`.
+///
+/// The schedule is never stored: every repetition regenerates it from a generator of its own, seeded the same
+/// way, so it takes no more memory than a generator and is the same for any seed on every run. The streams from
+/// 0 upwards belong to the repetitions, and the last one to the bootstrap.
+pub const FIXED_SCHEDULE_STREAM: usize = usize::MAX - 1;
+
+/// Create the `index`-th RNG derived from `base` with [`seed_for`]; generators with a shorter seed
+/// take its first bytes
+fn derived_rng<R: SeedableRng>(base: u64, index: usize) -> R {
+    let mut seed = R::Seed::default();
+    for (byte, derived) in seed.as_mut().iter_mut().zip(seed_for(base, index)) {
+        *byte = derived;
+    }
+    R::from_seed(seed)
+}
+
+/// Derive the base seed of the repetitions of one corruption threshold from the seed of a sweep.
+///
+/// This only depends on the two arguments, so a threshold gets the same seed no matter which other
+/// thresholds are part of the sweep or in which order they are run.
+pub fn threshold_seed(seed: u64, fraction_corrupted: f64) -> u64 {
+    StdRng::seed_from_u64(seed ^ fraction_corrupted.to_bits()).gen()
+}
+
+/// Validate the configuration and return the number of corrupted cups and the target water level
+/// of a corruption threshold
+pub fn threshold_parameters(config: &SimulationConfig, fraction_corrupted_commitments: f64) -> Result<(usize, f64), SimError> {
+    config.validate()?;
+    if !(0.0..1.0).contains(&fraction_corrupted_commitments) {
+        return Err(SimError::InvalidFraction(fraction_corrupted_commitments));
+    }
+    let vector_length = config.vector_length;
+
+    let corrupted_commitments: usize = match &config.corrupted_cups {
+        Some(corrupted_cups) => corrupted_cups.len(),
+        None => config.corrupted_count.unwrap_or(((vector_length as f64) * fraction_corrupted_commitments) as usize),
+    };
+
+    // Target Water level
+    let target_eps: f64 = config.target_eps
+        .unwrap_or(config.eps_numerator / (vector_length as f64 * (1.0 - fraction_corrupted_commitments)));
+
+    Ok((corrupted_commitments, target_eps))
+}
+
+/// Run a single repetition for one corruption threshold, and return the highest water level of any
+/// cup in every round until the targets are hidden (or `config.max_shuffles` rounds have passed).
+///
+/// The repetition is seeded like repetition 0 of [`run_sim`] with the same arguments, so it is that
+/// repetition.
+pub fn trace_repetition(config: &SimulationConfig, fraction_corrupted_commitments: f64) -> Result<Vec<f64>, SimError> {
+    trace_repetition_with_rng::<StdRng>(config, fraction_corrupted_commitments)
+}
+
+/// Like [`trace_repetition`], drawing all random choices from an `R`, as [`run_sim_with_rng`] does
+pub fn trace_repetition_with_rng<R: Rng + SeedableRng>(config: &SimulationConfig, fraction_corrupted_commitments: f64)
+    -> Result<Vec<f64>, SimError> {
+    SimulationState::<R>::with_rng(config, fraction_corrupted_commitments, 0)?.trace()
+}
+
+/// Run all repetitions for one corruption threshold, or fail if the configuration or the threshold
+/// cannot be simulated.
+///
+/// `fraction_corrupted_commitments` of the cups are corrupted (exactly `config.corrupted_cups` or
+/// `config.corrupted_count` many if set, in which case the fraction should match their number). That
+/// count is nominal: with a `config.corruption_spread` other than `Fixed`, every repetition draws its
+/// own count around it, while the target level and the reported numbers stay those of the nominal count. The
+/// target counts as hidden once its water drops below `config.target_eps` if set, or else
+/// `config.eps_numerator / (vector_length * (1 - fraction_corrupted_commitments))`. With
+/// [`SuccessCriterion::Unlinkable`], it is the difference between the targets that has to drop below it.
+///
+/// The repetitions advance a round at a time, so that with `config.early_exit` the simulation can
+/// stop as soon as every repetition has the targets hidden in the same round. Only
+/// [`SimulationConfig::repetitions_at_once`] of them are simulated at once; chunks that stop before
+/// another chunk are resumed from where they stopped, so the result is the same (up to rounding of
+/// the mean differences between secrets) as that of all repetitions together, which the stream of
+/// [`run_sim_streaming`] runs.
+/// Repetition `i` uses its own RNG seeded with `seed_for(config.seed, i)`, so the outcome only depends
+/// on the configuration and not on the number of threads, and the repetitions are independent.
+pub fn run_sim(config: &SimulationConfig, fraction_corrupted_commitments: f64) -> Result<SimulationResult, SimError> {
+    run_sim_with_rng::<StdRng>(config, fraction_corrupted_commitments)
+}
+
+/// Like [`run_sim`], with every repetition drawing its random choices from an `R` instead of a
+/// [`StdRng`], e.g. a faster `SmallRng`. The RNGs are seeded the same way, but the outcome differs
+/// from that of `run_sim` as the generators produce different streams.
+pub fn run_sim_with_rng<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, fraction_corrupted_commitments: f64)
+    -> Result<SimulationResult, SimError> {
+    let (corrupted_commitments, target_eps) = threshold_parameters(config, fraction_corrupted_commitments)?;
+    let eps_numerator = config.target_eps.is_none().then_some(config.eps_numerator);
+    Ok(run_in_chunks::<R>(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, vec![target_eps])?.swap_remove(0))
+}
+
+/// Like [`run_sim`], calling `callback` after every shuffle of every repetition, if there is one, see
+/// [`SimulationStream::with_callback`]. Without a callback, this is `run_sim` itself.
+pub fn run_sim_with_callback(config: &SimulationConfig, fraction_corrupted_commitments: f64, callback: Option<&ShuffleCallback>)
+    -> Result<SimulationResult, SimError> {
+    match callback {
+        Some(callback) => run_sim_streaming(config, fraction_corrupted_commitments)?.with_callback(callback).finish(),
+        None => run_sim_with_rng::<StdRng>(config, fraction_corrupted_commitments),
+    }
+}
+
+/// Called by a [`SimulationStream`] with the (1-indexed) round, the (1-indexed) shuffle within that round, the
+/// repetition and its highest water level after that shuffle, before the adversary acts at the end of the round.
+/// Returning [`ControlFlow::Break`] stops the simulation once the round is done.
+///
+/// Besides the round, repetition and water level, it gets the shuffle, as a round has `shuffles_per_round` of them.
+pub type ShuffleCallback<'a> = dyn Fn(usize, usize, usize, f64) -> ControlFlow<()> + Sync + 'a;
+
+/// Totals over all repetitions of [`run_sim`] after one round, as yielded by [`run_sim_streaming`]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RoundUpdate {
+    /// The (1-indexed) round
+    pub round: usize,
+    /// Highest water level of any cup in any repetition
+    pub max_water: f64,
+    /// Number of repetitions in which the targets are hidden
+    pub success_count: u64,
+    /// Number of cups holding more than `target_eps` of the water of a target, summed over targets and repetitions
+    pub cups_above_eps: u64,
+    /// Mean over repetitions of the largest difference between the water of two targets in any cup
+    pub secret_difference: f64,
+}
+
+/// Start the simulation of one corruption threshold without running any round yet, or fail if the
+/// configuration or the threshold cannot be simulated.
+///
+/// The returned stream runs one more round of all repetitions every time it is advanced, and yields
+/// their totals, so that the progress can be shown while the simulation runs. It ends after the
+/// round in which every repetition has the targets hidden (with `config.early_exit`), after
+/// `config.max_shuffles` rounds, or after an error. [`SimulationStream::finish`] then gives the
+/// same result as [`run_sim`]. The totals of a round need every repetition, so the stream simulates
+/// all of them at once, whatever `config.repetition_chunk`.
+pub fn run_sim_streaming(config: &SimulationConfig, fraction_corrupted_commitments: f64) -> Result<SimulationStream<'_>, SimError> {
+    run_sim_streaming_with_rng::<StdRng>(config, fraction_corrupted_commitments)
+}
+
+/// Like [`run_sim_streaming`], as [`run_sim_with_rng`] is to [`run_sim`]
+pub fn run_sim_streaming_with_rng<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, fraction_corrupted_commitments: f64)
+    -> Result<SimulationStream<'_, R>, SimError> {
+    let (corrupted_commitments, target_eps) = threshold_parameters(config, fraction_corrupted_commitments)?;
+    let eps_numerator = config.target_eps.is_none().then_some(config.eps_numerator);
+    Ok(SimulationStream::new(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, vec![target_eps], 0..config.repetitions))
+}
+
+/// Run all repetitions of one corruption threshold once, and return the result of every target level
+/// in `target_eps`, in the same order, or fail if the configuration, the threshold or any of the
+/// levels cannot be simulated.
+///
+/// The water spreads the same way whatever level the targets have to drop below, so this only
+/// shuffles once: the result of each level is the same as that of [`run_sim`] with `config.target_eps`
+/// set to it. The simulation goes on until the last level is done, e.g. with `config.early_exit`
+/// until the targets are hidden in every repetition under the lowest level.
+pub fn run_sim_for_eps(config: &SimulationConfig, fraction_corrupted_commitments: f64, target_eps: &[f64])
+    -> Result<Vec<SimulationResult>, SimError> {
+    run_sim_for_eps_with_rng::<StdRng>(config, fraction_corrupted_commitments, target_eps)
+}
+
+/// Like [`run_sim_for_eps`], as [`run_sim_with_rng`] is to [`run_sim`]
+pub fn run_sim_for_eps_with_rng<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, fraction_corrupted_commitments: f64,
+                                                                    target_eps: &[f64]) -> Result<Vec<SimulationResult>, SimError> {
+    let (corrupted_commitments, _) = threshold_parameters(config, fraction_corrupted_commitments)?;
+    if let Some(&eps) = target_eps.iter().find(|&&eps| !(eps > 0.0 && eps < 1.0)) {
+        return Err(SimError::InvalidTargetEps(eps));
+    }
+    if target_eps.is_empty() {
+        return Ok(Vec::new());
+    }
+    run_in_chunks::<R>(config, fraction_corrupted_commitments, corrupted_commitments, None, target_eps.to_vec())
+}
+
+/// All repetitions of one corruption threshold, advanced in lockstep, see [`run_sim_streaming`]
+pub struct SimulationStream<'a, R = StdRng> {
+    config: &'a SimulationConfig,
+    fraction_corrupted_commitments: f64,
+    corrupted_commitments: usize,
+    eps_numerator: Option<f64>,
+    /// Levels the targets have to drop below, the first one being the one the stream yields
+    target_eps: Vec<f64>,
+    /// All repetitions, or a chunk of them when [`run_in_chunks`] runs it
+    states: Vec<SimulationState<'a, R>>,
+    /// Round from which every target level can be done early, as no earlier round hides the targets in every chunk
+    earliest_exit: Vec<usize>,
+    /// Round after which no more rounds are run
+    last_round: usize,
+    /// Number of rounds simulated so far
+    round: usize,
+    /// Totals of every round so far, for every target level until it was done
+    updates: Vec<Vec<RoundUpdate>>,
+    /// What every repetition contributed to every target level that is done
+    outcomes: Vec<Option<Vec<RepetitionOutcome>>>,
+    /// Set once a round failed
+    failed: bool,
+    /// Called after every shuffle of every repetition, see [`SimulationStream::with_callback`]
+    callback: Option<&'a ShuffleCallback<'a>>,
+}
+
+/// The rounds and repetitions of every target level of a [`SimulationStream`], once it is done
+struct Levels {
+    /// Totals of every round up to the one the level was done in
+    updates: Vec<Vec<RoundUpdate>>,
+    /// What every repetition contributed to the level
+    outcomes: Vec<Vec<RepetitionOutcome>>,
+}
+
+impl<'a, R: Rng + SeedableRng + Send + Sync> SimulationStream<'a, R> {
+    /// Set up `repetitions` of a threshold, whose parameters were already validated by [`threshold_parameters`]
+    fn new(config: &'a SimulationConfig, fraction_corrupted_commitments: f64, corrupted_commitments: usize, eps_numerator: Option<f64>,
+           target_eps: Vec<f64>, repetitions: Range<usize>) -> Self {
+        let max_shuffles = config.max_shuffles;
+        debug!("fraction {fraction_corrupted_commitments}: repetitions {repetitions:?} of {}, {corrupted_commitments} corrupted cups, \
+                target eps {target_eps:?}", config.repetitions);
+
+        let states = repetitions.into_par_iter()
+            .map(|repetition| SimulationState::with_parameters(config, fraction_corrupted_commitments, corrupted_commitments, target_eps.clone(),
+                                                               repetition))
+            .collect();
+        let levels = target_eps.len();
+        SimulationStream { config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, target_eps, states,
+                           earliest_exit: vec![0; levels], last_round: max_shuffles, round: 0,
+                           updates: vec![Vec::with_capacity(max_shuffles); levels], outcomes: vec![None; levels], failed: false,
+                           callback: None }
+    }
+
+    /// Go on from the round the stream stopped in without exiting early before `earliest_exit`, and stop after `last_round`,
+    /// neither of which is before that round; only a stream of one target level can be resumed, as the others would have
+    /// had to go on in rounds that already passed
+    fn resume(&mut self, earliest_exit: usize, last_round: usize) {
+        debug_assert_eq!(self.target_eps.len(), 1, "only a stream of one target level can be resumed");
+        if self.round < earliest_exit.min(last_round) {
+            self.outcomes[0] = None;
+        }
+        self.earliest_exit = vec![earliest_exit];
+        self.last_round = last_round;
+    }
+
+    /// Call `callback` after every shuffle of every repetition, e.g. for logging or metrics of its own.
+    ///
+    /// The repetitions of a round run in parallel, so the calls come from several threads in any order,
+    /// but the calls of a repetition come shuffle by shuffle, and every call of a round happens before
+    /// any of the next round. Once a call returns [`ControlFlow::Break`], the stream ends after that
+    /// round as if it were the last one.
+    pub fn with_callback(mut self, callback: &'a ShuffleCallback<'a>) -> Self {
+        self.callback = Some(callback);
+        self
+    }
+
+    /// Whether another round is run for any target level
+    fn is_running(&self) -> bool {
+        !self.failed && self.round < self.last_round && self.outcomes.iter().any(Option::is_none)
+    }
+
+    /// Run the next round of all repetitions, and record its totals for every target level that is not done yet
+    fn advance(&mut self) -> Result<(), SimError> {
+        let SimulationConfig { repetitions, early_exit, .. } = *self.config;
+        let (levels, simulated) = (self.target_eps.len(), self.states.len());
+        let round = self.round + 1;
+
+        // Number of repetitions in which the targets are hidden in this round and the number of cups above the
+        // target level (for every level), the highest water level and the summed difference between targets
+        let totals = || (vec![0; levels], 0.0, vec![0; levels], 0.0);
+        let stopped = AtomicBool::new(false);
+        let callback = self.callback;
+        let (successes, max_water, cups_above_eps, secret_difference) = self.states.par_iter_mut()
+            .fold(totals, |(mut successes, max_water, mut cups_above_eps, secret_difference), state| {
+                let repetition = state.repetition;
+                let outcome = match callback {
+                    Some(callback) => state.run_round(Some(&mut |shuffle, max_water| {
+                        if callback(round, shuffle, repetition, max_water).is_break() {
+                            stopped.store(true, Ordering::Relaxed);
+                        }
+                    })),
+                    None => state.run_round(None),
+                };
+                for level in 0..levels {
+                    successes[level] += state.hidden_since[level].is_some() as u64;
+                    cups_above_eps[level] += state.cups_above_eps[level];
+                }
+                (successes, nan_max(max_water, outcome.max_water), cups_above_eps, secret_difference + outcome.secret_difference)
+            })
+            .reduce(totals, |(mut a, max_a, mut above_a, diff_a), (b, max_b, above_b, diff_b)| {
+                for level in 0..levels {
+                    a[level] += b[level];
+                    above_a[level] += above_b[level];
+                }
+                (a, nan_max(max_a, max_b), above_a, diff_a + diff_b)
+            });
+        if !max_water.is_finite() {
+            self.failed = true;
+            return Err(SimError::NonFiniteWater { round });
+        }
+        self.round = round;
+        if round % 500 == 1 {
+            debug!("fraction {}: round {round} hidden in {}/{simulated} repetitions", self.fraction_corrupted_commitments, successes[0]);
+        }
+
+        // Once no repetition is unfinished, the success round of a level is known and further rounds change nothing
+        // about it, but the targets still have to be opened
+        let opened = self.config.target_opening.is_none_or(|opening| round >= opening.round);
+        for level in 0..levels {
+            if self.outcomes[level].is_some() {
+                continue;
+            }
+            // The difference is averaged over all repetitions, so that the totals of chunks add up
+            self.updates[level].push(RoundUpdate { round, max_water, success_count: successes[level], cups_above_eps: cups_above_eps[level],
+                                                   secret_difference: secret_difference / repetitions as f64 });
+            let hidden = early_exit && round >= self.earliest_exit[level] && successes[level] == simulated as u64 && opened;
+            if hidden || round == self.last_round || stopped.load(Ordering::Relaxed) {
+                self.outcomes[level] = Some(self.repetition_outcomes(level));
+            }
+        }
+        Ok(())
+    }
+
+    /// What every repetition contributes to target level `level`, as of the rounds simulated so far
+    fn repetition_outcomes(&self, level: usize) -> Vec<RepetitionOutcome> {
+        self.states.par_iter().map(|state| state.outcome(level)).collect()
+    }
+
+    /// Run the rounds that are left, and return the outcome of the simulation
+    pub fn finish(self) -> Result<SimulationResult, SimError> {
+        Ok(self.finish_all()?.swap_remove(0))
+    }
+
+    /// Run the rounds that are left for any target level, and return the outcome of every level
+    fn finish_all(self) -> Result<Vec<SimulationResult>, SimError> {
+        let SimulationStream { config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, .. } = self;
+        let target_eps = self.target_eps.clone();
+        let levels = self.finish_levels()?;
+        Ok(level_results::<R>(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, &target_eps, &levels))
+    }
+
+    /// Run the rounds that are left for any target level
+    fn run_out(&mut self) -> Result<(), SimError> {
+        while self.is_running() {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
+    /// Run the rounds that are left for any target level, and return its rounds and repetitions
+    fn finish_levels(mut self) -> Result<Levels, SimError> {
+        self.run_out()?;
+        // Only a simulation without any rounds leaves levels that were never done
+        let outcomes = (0..self.target_eps.len())
+            .map(|level| self.outcomes[level].take().unwrap_or_else(|| self.repetition_outcomes(level)))
+            .collect();
+        Ok(Levels { updates: self.updates, outcomes })
+    }
+}
+
+/// Simulate the repetitions of a threshold, whose parameters were already validated by [`threshold_parameters`], in chunks
+/// of [`SimulationConfig::repetitions_at_once`], and return the outcome of every level of `target_eps`.
+///
+/// All repetitions together are only done with a level early in a round in which every chunk has the targets hidden, so
+/// a chunk that is done before another one has to go on until then. Every chunk is resumed from the round it stopped in
+/// until it is done no earlier than the latest round any chunk was done in so far, until all of them are done in the same
+/// round. That is the round in which the repetitions would have been done together, so the outcome is the same. Chunks
+/// are resumed one target level at a time, as a chunk that stopped for a later level is past the rounds of the others.
+fn run_in_chunks<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, fraction_corrupted_commitments: f64, corrupted_commitments: usize,
+                                                     eps_numerator: Option<f64>, target_eps: Vec<f64>) -> Result<Vec<SimulationResult>, SimError> {
+    let (repetitions, chunk) = (config.repetitions, config.repetitions_at_once());
+    let stream = |repetitions: Range<usize>| {
+        SimulationStream::<R>::new(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, target_eps.clone(), repetitions)
+    };
+    let results = |levels: &Levels| level_results::<R>(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, &target_eps, levels);
+    if chunk >= repetitions {
+        return Ok(results(&stream(0..repetitions).finish_levels()?));
+    }
+    if target_eps.len() > 1 {
+        return target_eps.iter()
+            .map(|&eps| Ok(run_in_chunks::<R>(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, vec![eps])?.swap_remove(0)))
+            .collect();
+    }
+
+    let chunks: Vec<Range<usize>> = (0..repetitions).step_by(chunk).map(|start| start..repetitions.min(start + chunk)).collect();
+    let mut earliest_exit = 0;
+    let mut streams: Vec<Option<SimulationStream<R>>> = chunks.iter().map(|_| None).collect();
+    while !streams.iter().all(|paused| paused.as_ref().is_some_and(|paused| paused.round == earliest_exit)) {
+        for (index, range) in chunks.iter().enumerate() {
+            let mut chunk = match streams[index].take() {
+                Some(mut paused) => {
+                    paused.resume(earliest_exit, config.max_shuffles);
+                    paused
+                }
+                None => stream(range.clone()),
+            };
+            match chunk.run_out() {
+                Ok(()) => {}
+                Err(SimError::NonFiniteWater { round }) => return Err(first_non_finite_round(&chunks, index, round, streams, stream)),
+                Err(error) => return Err(error),
+            }
+            earliest_exit = earliest_exit.max(chunk.round);
+            streams[index] = Some(chunk);
+        }
+    }
+    debug!("fraction {fraction_corrupted_commitments}: {} chunks of {chunk} repetitions done in round {earliest_exit}", chunks.len());
+
+    // Every chunk simulated the same rounds, so their totals add up round by round
+    let done = streams.into_iter().flatten().map(SimulationStream::finish_levels).collect::<Result<Vec<Levels>, SimError>>()?;
+    let mut merged = Levels { updates: done[0].updates.clone(), outcomes: vec![Vec::with_capacity(repetitions)] };
+    for levels in &done {
+        merged.outcomes[0].extend_from_slice(&levels.outcomes[0]);
+    }
+    for levels in &done[1..] {
+        for (total, update) in merged.updates[0].iter_mut().zip(&levels.updates[0]) {
+            total.max_water = nan_max(total.max_water, update.max_water);
+            total.success_count += update.success_count;
+            total.cups_above_eps += update.cups_above_eps;
+            total.secret_difference += update.secret_difference;
+        }
+    }
+    Ok(results(&merged))
+}
+
+/// Return the error of the first round in which any repetition holds infinite or NaN water, given that chunk `failed`
+/// does in `round` (while the repetitions together were not done yet) and the chunks that were run, whose `streams` are
+/// paused, are finite up to the round they stopped in
+fn first_non_finite_round<'a, R: Rng + SeedableRng + Send + Sync>(chunks: &[Range<usize>], failed: usize, round: usize,
+                                                                  streams: Vec<Option<SimulationStream<'a, R>>>,
+                                                                  stream: impl Fn(Range<usize>) -> SimulationStream<'a, R>) -> SimError {
+    let mut first = round;
+    for (index, (range, paused)) in chunks.iter().zip(streams).enumerate() {
+        let finite_until = paused.as_ref().map_or(0, |paused| paused.round);
+        if index == failed || finite_until + 1 >= first {
+            continue;
+        }
+        let mut chunk = paused.unwrap_or_else(|| stream(range.clone()));
+        chunk.resume(first - 1, first - 1);
+        if let Err(SimError::NonFiniteWater { round }) = chunk.run_out() {
+            first = round;
+        }
+    }
+    SimError::NonFiniteWater { round: first }
+}
+
+/// Outcome of every target level of `target_eps` of a threshold, from its `levels`
+fn level_results<R: Rng + SeedableRng>(config: &SimulationConfig, fraction_corrupted_commitments: f64, corrupted_commitments: usize,
+                                 eps_numerator: Option<f64>, target_eps: &[f64], levels: &Levels) -> Vec<SimulationResult> {
+    target_eps.iter().zip(levels.updates.iter().zip(&levels.outcomes))
+        .map(|(&target_eps, (updates, outcomes))| {
+            level_result::<R>(config, fraction_corrupted_commitments, corrupted_commitments, eps_numerator, target_eps, updates, outcomes)
+        })
+        .collect()
+}
+
+/// Outcome of the simulation for one target level, from the totals of its rounds and what every repetition contributed
+fn level_result<R: Rng + SeedableRng>(config: &SimulationConfig, fraction_corrupted_commitments: f64, corrupted_commitments: usize,
+                                eps_numerator: Option<f64>, target_eps: f64, updates: &[RoundUpdate], outcomes: &[RepetitionOutcome])
+    -> SimulationResult {
+    let SimulationConfig { vector_length, shuffle_size, max_shuffles, repetitions, seed, .. } = *config;
+
+    // Rounds after an early exit count as having no successes, as before early exits were possible
+    let mut sum_succ_in_round = vec![0; max_shuffles];
+    let mut cups_above_eps_in_round = vec![0; max_shuffles];
+    let mut secret_difference_in_round = vec![0.0; max_shuffles];
+    for (t, update) in updates.iter().enumerate() {
+        sum_succ_in_round[t] = update.success_count;
+        cups_above_eps_in_round[t] = update.cups_above_eps;
+        secret_difference_in_round[t] = update.secret_difference;
+    }
+    let final_successes = updates.last().map_or(0, |update| update.success_count);
+
+    // A repetition stays hidden in the rounds from its hiding round on, which never counts more
+    // successes than counting every round does
+    let hiding_rounds: Vec<Option<usize>> = outcomes.iter().map(|outcome| outcome.hiding_round).collect();
+    let mut lasting_successes = vec![0; max_shuffles];
+    for &round in hiding_rounds.iter().flatten() {
+        for successes in &mut lasting_successes[round - 1..updates.len()] {
+            *successes += 1;
+        }
+    }
+    let transient_successes = sum_succ_in_round.iter().sum::<u64>() - lasting_successes.iter().sum::<u64>();
+    if config.success_counting == SuccessCounting::StaysHidden {
+        sum_succ_in_round = lasting_successes;
+    }
+    // All repetitions simulated the same rounds, so they did the same number of shuffles
+    let shuffles = repetitions * updates.len() * config.shuffles_per_round;
+    let honest_shuffled: usize = outcomes.iter().map(|outcome| outcome.honest_shuffled).sum();
+    let mean_honest_per_shuffle = if shuffles == 0 { 0.0 } else { honest_shuffled as f64 / shuffles as f64 };
+    let success_round = get_success_round(&sum_succ_in_round, repetitions, config.success_probability);
+    let probability_rounds = get_success_rounds(&sum_succ_in_round, repetitions, &PROBABILITY_LEVELS);
+    let max_water_at_success = success_round.map(|round| updates[round - 1].max_water);
+    // The streams from 0 upwards belong to the repetitions, so the bootstrap takes the last one
+    let mut bootstrap_rng: R = derived_rng(seed, usize::MAX);
+    let mean_round_interval = bootstrap_mean_interval(&hiding_rounds, config.bootstrap_resamples, &mut bootstrap_rng);
+    let entropy: f64 = outcomes.iter().map(|outcome| outcome.entropy).sum();
+    let gini: f64 = outcomes.iter().map(|outcome| outcome.gini).sum();
+    let opened: Vec<bool> = outcomes.iter().filter_map(|outcome| outcome.hidden_when_opened).collect();
+
+    SimulationResult {
+        vector_length,
+        shuffle_size,
+        fraction_corrupted: fraction_corrupted_commitments,
+        corrupted_commitments,
+        eps_numerator,
+        target_eps,
+        success_round,
+        probability_rounds,
+        success_shuffles: success_round.map(|round| round * config.shuffles_per_round),
+        successes_in_round: sum_succ_in_round,
+        transient_successes,
+        mean_crossings: outcomes.iter().map(|outcome| outcome.crossings).sum::<u64>() as f64 / repetitions as f64,
+        hiding_round_stats: RoundStats::from_rounds(&hiding_rounds),
+        max_water_at_success,
+        eps_ratio: max_water_at_success.map(|max_water| max_water / target_eps),
+        final_success_probability: final_successes as f64 / repetitions as f64,
+        cups_above_eps_in_round,
+        secret_difference_in_round,
+        entropy: entropy / repetitions as f64,
+        gini: gini / repetitions as f64,
+        mean_honest_per_shuffle,
+        max_mass_drift: config.track_mass.then(|| outcomes.iter().map(|outcome| outcome.max_mass_drift).fold(0.0, nan_max)),
+        targets_opened: opened.len() as u64,
+        safe_when_opened: opened.iter().filter(|&&hidden| hidden).count() as u64,
+        mean_round_interval,
+        hiding_rounds,
+    }
+}
+
+impl<R: Rng + SeedableRng + Send + Sync> Iterator for SimulationStream<'_, R> {
+    type Item = Result<RoundUpdate, SimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.is_running() || self.outcomes[0].is_some() {
+            return None;
+        }
+        Some(self.advance().map(|()| *self.updates[0].last().expect("a round was just run")))
+    }
+}
+
+/// Find the smallest shuffle size whose success round is at most `deadline`, and return it with its
+/// result, or `None` if the targets are not hidden in time even when every shuffle covers all cups.
+///
+/// This binary-searches the shuffle sizes from 2 (a single cup never mixes) up to `vector_length`,
+/// with one [`run_sim`] per candidate, and so assumes that larger batches never hide more slowly.
+/// `config.shuffle_size` is ignored, every candidate only simulates up to `deadline` rounds, and all
+/// of them use the same `config.seed`, so that they are compared on the same corrupted cups.
+pub fn find_min_shuffle_size(config: &SimulationConfig, fraction_corrupted_commitments: f64, deadline: usize)
+    -> Result<Option<SimulationResult>, SimError> {
+    find_min_shuffle_size_with_rng::<StdRng>(config, fraction_corrupted_commitments, deadline)
+}
+
+/// Like [`find_min_shuffle_size`], running every candidate with [`run_sim_with_rng`]
+pub fn find_min_shuffle_size_with_rng<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, fraction_corrupted_commitments: f64,
+                                                                          deadline: usize) -> Result<Option<SimulationResult>, SimError> {
+    let simulate = |shuffle_size: usize| {
+        let candidate = SimulationConfig { shuffle_size, max_shuffles: deadline, ..config.clone() };
+        let result = run_sim_with_rng::<R>(&candidate, fraction_corrupted_commitments)?;
+        debug!("fraction {fraction_corrupted_commitments}: shuffle size {shuffle_size} hides in round {:?}", result.success_round);
+        Ok::<_, SimError>(result)
+    };
+
+    let largest = simulate(config.vector_length)?;
+    if largest.success_round.is_none() {
+        return Ok(None);
+    }
+
+    // `best` always holds the result of the smallest size found to succeed, `low` the smallest candidate left
+    let (mut low, mut best) = (2.min(config.vector_length), largest);
+    while low < best.shuffle_size {
+        let middle = low + (best.shuffle_size - low) / 2;
+        let result = simulate(middle)?;
+        if result.success_round.is_some() {
+            best = result;
+        } else {
+            low = middle + 1;
+        }
+    }
+    Ok(Some(best))
+}
+
+/// Boundary between the corruption fractions that hide the targets within `max_shuffles` rounds and
+/// those that do not, as found by [`find_critical_threshold`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CriticalThreshold {
+    /// Result of the highest corruption fraction found to hide the targets in time
+    pub hidden: SimulationResult,
+    /// Lowest corruption fraction found not to hide them in time, at most the precision of the search above
+    /// `hidden`, or 1 if every fraction that was simulated hid them
+    pub never_hidden: f64,
+}
+
+/// Find the corruption fraction above which the targets are no longer hidden within `config.max_shuffles`
+/// rounds, to within `precision`, or return `None` if they are not hidden in time even without corruption.
+///
+/// This bisects the fractions in `[0, 1)`, with one [`run_sim`] per candidate, and so assumes that more
+/// corruption never hides faster. Every candidate is seeded with [`threshold_seed`] of `config.seed`, so that its
+/// result is the same as for that threshold of a sweep. `precision` must be positive, but the search also stops
+/// once the candidates are too close to tell apart.
+pub fn find_critical_threshold(config: &SimulationConfig, precision: f64) -> Result<Option<CriticalThreshold>, SimError> {
+    find_critical_threshold_with_rng::<StdRng>(config, precision)
+}
+
+/// Like [`find_critical_threshold`], running every candidate with [`run_sim_with_rng`]
+pub fn find_critical_threshold_with_rng<R: Rng + SeedableRng + Send + Sync>(config: &SimulationConfig, precision: f64)
+    -> Result<Option<CriticalThreshold>, SimError> {
+    let simulate = |fraction_corrupted_commitments: f64| {
+        let candidate = SimulationConfig { seed: threshold_seed(config.seed, fraction_corrupted_commitments), ..config.clone() };
+        let result = run_sim_with_rng::<R>(&candidate, fraction_corrupted_commitments)?;
+        debug!("fraction {fraction_corrupted_commitments}: hides in round {:?}", result.success_round);
+        Ok::<_, SimError>(result)
+    };
+
+    let uncorrupted = simulate(0.0)?;
+    if uncorrupted.success_round.is_none() {
+        return Ok(None);
+    }
+
+    // `hidden` always holds the result of the highest fraction found to succeed, `high` the lowest one found to
+    // fail; a vector without honest cups hides nothing, so the search starts below 1
+    let (mut hidden, mut high) = (uncorrupted, 1.0);
+    while high - hidden.fraction_corrupted > precision {
+        let middle = hidden.fraction_corrupted + (high - hidden.fraction_corrupted) / 2.0;
+        if middle <= hidden.fraction_corrupted || middle >= high {
+            break;
+        }
+        let result = simulate(middle)?;
+        if result.success_round.is_some() {
+            hidden = result;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(Some(CriticalThreshold { hidden, never_hidden: high }))
+}
@@ -1,137 +1,1999 @@
-use std::collections::HashSet;
-use rand::{seq::IteratorRandom, thread_rng};
-use rand::rngs::ThreadRng;
-use hashbrown::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use rand::rngs::{SmallRng, StdRng};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use clap::{Arg, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap::parser::ValueSource;
+use clap::error::ErrorKind;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::warn;
+use rayon::prelude::*;
+use serde::Serialize;
+use aqua_shuffle_sim::{
+    find_critical_threshold, find_critical_threshold_with_rng, find_min_shuffle_size, find_min_shuffle_size_with_rng, rank_sum_p_value,
+    run_sim, run_sim_for_eps, run_sim_for_eps_with_rng, run_sim_streaming_with_rng, run_sim_with_rng, threshold_parameters, threshold_seed,
+    AdversaryStrategy, BatchScheduler, CorruptionSpread, CriticalThreshold, InitialDistribution, MixRuleKind, RoundStats, RoundUpdate,
+    SelectionWeights, ShuffleModel, SimError, SizeDist, SimulationConfig, SuccessCounting, SuccessCriterion, SimulationResult,
+    SimulationState, StateSnapshot, Summation, TargetOpening, MAX_SHUFFLES, PROBABILITY_LEVELS, NUMBER_OF_REPETITIONS, SHUFFLE_SIZE,
+    VECTOR_LENGTH
+};
 
-const VECTOR_LENGTH: usize = 2_usize.pow(14);
+/// Format in which simulation results are written
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable `Simulation parameters: ...` lines
+    Text,
+    /// Header row followed by one row per corruption threshold
+    Csv,
+    /// Versioned document with one record per corruption threshold, see `JSON_SCHEMA_VERSION`
+    Json,
+    /// Success rounds as a grid, with a row per shuffle size and a column per corruption threshold
+    Matrix,
+    /// The exact lines of the historical results dumps, `Simulation parameters: [16384 128] [0.01 0.000246...]: 1892`,
+    /// with a success round of 0 if the targets were never hidden; kept as it is for the scripts that parse them
+    Legacy,
+}
 
-// Size of one local shuffle
-const SHUFFLE_SIZE: usize = 128;
+/// Adversary strategy selectable on the command line
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Adversary {
+    /// Corrupt a random set of cups once per repetition
+    StaticRandom,
+    /// Periodically move part of the corruption budget to the cups holding the most water
+    AdaptiveGreedy,
+}
 
-// Upper bound on number of shuffles in protocol execution
-const MAX_SHUFFLES: usize = 4000;
+/// Variation of the corruption budget selectable on the command line
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Spread {
+    /// Corrupt exactly the nominal number of cups in every repetition
+    Fixed,
+    /// Corrupt every cup independently, with the nominal fraction as probability
+    Binomial,
+    /// Draw the number of corrupted cups of every repetition from a Poisson distribution around the nominal count
+    Poisson,
+}
 
-// Number of repetitions over which the average benchmark outcomes are computed
-const NUMBER_OF_REPETITIONS: usize = 1000;
+/// Random number generator selectable on the command line
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Generator {
+    /// `SmallRng`, which is faster but not cryptographically secure
+    Small,
+    /// `StdRng`, which the fixed-seed results of earlier versions were computed with
+    Std,
+    /// `ChaCha20Rng`, the full-round cipher that `StdRng` is a faster variant of
+    Chacha,
+}
 
-/// Distribute water in uncorrupted cups of a given batch
-fn distribute_water(cups: &mut HashMap<usize, f64>, corrupted: &HashSet<usize>, rng: &mut ThreadRng) {
-    let shuffled_batch: HashSet<usize> = HashSet::from_iter((0..VECTOR_LENGTH).choose_multiple(rng, SHUFFLE_SIZE));
+impl Generator {
+    /// Run [`run_sim`] with this generator
+    fn run_sim(self, config: &SimulationConfig, fraction_corrupted_commitments: f64) -> Result<SimulationResult, SimError> {
+        match self {
+            Generator::Small => run_sim_with_rng::<SmallRng>(config, fraction_corrupted_commitments),
+            Generator::Std => run_sim(config, fraction_corrupted_commitments),
+            Generator::Chacha => run_sim_with_rng::<ChaCha20Rng>(config, fraction_corrupted_commitments),
+        }
+    }
 
-    // Get set of honest indices that will get shuffled
-    let honest_set = &shuffled_batch - corrupted;
-    let num_honest = honest_set.len();
-    if num_honest == 0 {
-        println!("no honest commitment selected!");
-        return;
+    /// Run [`write_stream`] with this generator
+    fn write_stream(self, out: &mut dyn Write, config: &SimulationConfig, fraction_corrupted_commitments: f64) -> io::Result<()> {
+        match self {
+            Generator::Small => write_stream::<SmallRng>(out, config, fraction_corrupted_commitments),
+            Generator::Std => write_stream::<StdRng>(out, config, fraction_corrupted_commitments),
+            Generator::Chacha => write_stream::<ChaCha20Rng>(out, config, fraction_corrupted_commitments),
+        }
+    }
+
+    /// Run [`run_sim_for_eps`] with this generator
+    fn run_sim_for_eps(self, config: &SimulationConfig, fraction_corrupted_commitments: f64, target_eps: &[f64])
+        -> Result<Vec<SimulationResult>, SimError> {
+        match self {
+            Generator::Small => run_sim_for_eps_with_rng::<SmallRng>(config, fraction_corrupted_commitments, target_eps),
+            Generator::Std => run_sim_for_eps(config, fraction_corrupted_commitments, target_eps),
+            Generator::Chacha => run_sim_for_eps_with_rng::<ChaCha20Rng>(config, fraction_corrupted_commitments, target_eps),
+        }
+    }
+
+    /// Run [`trace_state`] with this generator
+    fn trace_repetition(self, config: &SimulationConfig, fraction_corrupted_commitments: f64, resume: Option<&StateSnapshot>)
+        -> Result<(Vec<f64>, StateSnapshot), SimError> {
+        match self {
+            Generator::Small => trace_state::<SmallRng>(config, fraction_corrupted_commitments, resume),
+            Generator::Std => trace_state::<StdRng>(config, fraction_corrupted_commitments, resume),
+            Generator::Chacha => trace_state::<ChaCha20Rng>(config, fraction_corrupted_commitments, resume),
+        }
     }
 
-    // Find out how much total water we are distributing
-    let mut total_water: f64 = 0.0;
-    for v in honest_set.iter() {
-        total_water += cups.get(v).unwrap_or(&0.0);
+    /// Run [`find_min_shuffle_size`] with this generator
+    fn find_min_shuffle_size(self, config: &SimulationConfig, fraction_corrupted_commitments: f64, deadline: usize)
+        -> Result<Option<SimulationResult>, SimError> {
+        match self {
+            Generator::Small => find_min_shuffle_size_with_rng::<SmallRng>(config, fraction_corrupted_commitments, deadline),
+            Generator::Std => find_min_shuffle_size(config, fraction_corrupted_commitments, deadline),
+            Generator::Chacha => find_min_shuffle_size_with_rng::<ChaCha20Rng>(config, fraction_corrupted_commitments, deadline),
+        }
     }
-    let avg_water = total_water / (num_honest as f64);
 
-    // Pour water to all the cups
-    for index in honest_set {
-        cups.insert(index, avg_water);
+    /// Run [`find_critical_threshold`] with this generator
+    fn find_critical_threshold(self, config: &SimulationConfig, precision: f64) -> Result<Option<CriticalThreshold>, SimError> {
+        match self {
+            Generator::Small => find_critical_threshold_with_rng::<SmallRng>(config, precision),
+            Generator::Std => find_critical_threshold(config, precision),
+            Generator::Chacha => find_critical_threshold_with_rng::<ChaCha20Rng>(config, precision),
+        }
     }
 }
 
-/// Return the first round where we managed to perfectly hide the cup
-fn get_success_round(sum_succ_in_round: HashMap<usize, f64>) -> usize {
-//    println!("\n\tSuccess probability after rounds");
-//    println!("\t----------");
+/// Trace the first repetition of a threshold as [`aqua_shuffle_sim::trace_repetition`] does, or continue the repetition saved in
+/// `resume`, and return the trace along with the state after its last round
+fn trace_state<R: Rng + SeedableRng>(config: &SimulationConfig, fraction_corrupted_commitments: f64, resume: Option<&StateSnapshot>)
+    -> Result<(Vec<f64>, StateSnapshot), SimError> {
+    let mut state = match resume {
+        Some(snapshot) => SimulationState::<R>::from_snapshot_with_rng(config, snapshot)?,
+        None => SimulationState::<R>::with_rng(config, fraction_corrupted_commitments, 0)?,
+    };
+    let max_water = state.trace()?;
+    Ok((max_water, state.snapshot()))
+}
+
+/// Batch scheduler selectable on the command line
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Scheduler {
+    /// Draw every batch independently at random
+    RandomIid,
+    /// Tile a random permutation of all cups with disjoint batches, one epoch after the other
+    RoundRobinDisjoint,
+}
+
+/// Mixing rule selectable on the command line
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mixing {
+    /// Move every honest cup of a batch towards the average of the batch
+    Mean,
+    /// Pair up the honest cups of a batch and swap the water of every pair on the toss of a fair coin, ignoring
+    /// `--mixing-factor`
+    PairwiseSwap,
+}
+
+/// Simulate AquaShuffle against a fraction of corrupted cups
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// What to simulate, and what to write about it
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate every threshold of a sweep over corruption thresholds, 1% to 49% unless told otherwise, and write its results
+    Sweep(SweepArgs),
+
+    /// Simulate a single corruption threshold and write its results
+    Single(SingleArgs),
+
+    /// Run a single repetition per threshold and write its highest water level in every round until hiding as CSV
+    Trace(TraceArgs),
 
-    for t in 0..MAX_SHUFFLES {
-        // Success probability of current round
-        let round_success = sum_succ_in_round.get(&t).unwrap();
-        // Average success probability of previous round (should not underflow if t==0)
-        // let prev_round_success = sum_succ_in_round.get(&t.saturating_sub(1)).unwrap();
+    /// Compare the hiding rounds of two configurations, threshold by threshold
+    ///
+    /// Sweeps with the options before `--` (arm A) and with those after it replacing theirs (arm B), and writes the difference
+    /// in hiding rounds for every corruption threshold as CSV, e.g. `compare --shuffle-size 64 -- --shuffle-size 128`.
+    Compare(CompareArgs),
 
-        // if t == 0 || t == MAX_SHUFFLES - 1  || round_success != prev_round_success {
-        // Probability that shuffling completes in each round
-        //println!("\t{} \t {}",t+1, round_success / NUMBER_OF_REPETITIONS as f64);
-        //}
+    /// Binary-search the smallest shuffle size that hides the targets within `--deadline` rounds, and write it for every
+    /// corruption threshold as CSV
+    FindMinShuffleSize(FindMinArgs),
 
-        if round_success / NUMBER_OF_REPETITIONS as f64 == 1.0 {
-            return t+1;
+    /// Bisect the corruption fractions for the one above which the targets are no longer hidden within `--max-shuffles`
+    /// rounds, and write it with the success round just below it as CSV
+    CriticalThreshold(CriticalArgs),
+}
+
+/// Prefix of the environment variables that options fall back to, e.g. `AQUASHUFFLE_VECTOR_LENGTH` for `--vector-length`
+const ENV_PREFIX: &str = "AQUASHUFFLE_";
+
+/// Return the command line interface, where every option of every subcommand that is not given on the
+/// command line is read from its environment variable, and only falls back to its default without one
+fn cli_command() -> clap::Command {
+    env_command(&HashSet::new())
+}
+
+/// Like [`cli_command`], but the options that conflict with any of `given` (given on the command line) ignore their
+/// environment variables: clap would count those as given and reject the command line, when it should take precedence
+fn env_command(given: &HashSet<clap::Id>) -> clap::Command {
+    Cli::command().mut_subcommands(|subcommand| {
+        let conflicts = |arg: &Arg| {
+            subcommand.get_arg_conflicts_with(arg).iter().any(|other| given.contains(other.get_id()))
+                || subcommand.get_arguments().filter(|other| given.contains(other.get_id()))
+                       .any(|other| subcommand.get_arg_conflicts_with(other).iter().any(|conflict| conflict.get_id() == arg.get_id()))
+        };
+        let overridden: HashSet<clap::Id> = subcommand.get_arguments().filter(|&arg| conflicts(arg)).map(|arg| arg.get_id().clone()).collect();
+        subcommand.mut_args(|arg| match arg.get_long() {
+            Some(long) if !overridden.contains(arg.get_id()) => {
+                let name = format!("{ENV_PREFIX}{}", long.replace('-', "_").to_uppercase());
+                arg.env(name)
+            }
+            _ => arg,
+        })
+    })
+}
+
+/// Return the options of the subcommand that `argv` gives on the command line itself, tolerating any error, e.g. a
+/// missing option that an environment variable provides
+fn given_options(argv: &[OsString]) -> HashSet<clap::Id> {
+    let Ok(matches) = Cli::command().ignore_errors(true).try_get_matches_from(argv) else {
+        return HashSet::new();
+    };
+    let Some((_, matches)) = matches.subcommand() else {
+        return HashSet::new();
+    };
+    matches.ids().filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)).cloned().collect()
+}
+
+/// Parse `argv` with [`env_command`], exiting with a usage error if it is invalid
+fn cli_matches<I, T>(argv: I) -> ArgMatches
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let argv: Vec<OsString> = argv.into_iter().map(Into::into).collect();
+    env_command(&given_options(&argv)).try_get_matches_from(argv).unwrap_or_else(|error| error.exit())
+}
+
+/// Parse `argv` like [`cli_matches`] into a [`Cli`]
+fn parse_cli<I, T>(argv: I) -> Cli
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    Cli::from_arg_matches(&cli_matches(argv)).unwrap_or_else(|error| error.exit())
+}
+
+impl Command {
+    /// The options shared by every subcommand
+    fn options(&self) -> &Args {
+        match self {
+            Command::Sweep(sweep) => &sweep.options,
+            Command::Single(single) => &single.options,
+            Command::Trace(trace) => &trace.options,
+            Command::Compare(compare) => &compare.options,
+            Command::FindMinShuffleSize(find) => &find.options,
+            Command::CriticalThreshold(critical) => &critical.options,
         }
     }
+}
+
+/// Corruption thresholds of a sweep, unless a single one is given by `--corrupted-count` or `--corrupted-from`
+#[derive(clap::Args, Debug)]
+struct ThresholdArgs {
+    /// Lowest corruption threshold of the sweep, in percent
+    #[arg(long, default_value_t = 1.0, conflicts_with_all = ["corrupted_count", "corrupted_from"])]
+    corruption_min: f64,
+
+    /// Highest corruption threshold of the sweep, in percent
+    #[arg(long, default_value_t = 49.0, conflicts_with_all = ["corrupted_count", "corrupted_from"])]
+    corruption_max: f64,
+
+    /// Step between corruption thresholds of the sweep, in percent
+    #[arg(long, default_value_t = 1.0, conflicts_with_all = ["corrupted_count", "corrupted_from"])]
+    corruption_step: f64,
+
+    /// Simulate exactly these corrupted fractions of the cups in [0, 1), in the given order, instead of the range of
+    /// `--corruption-min` to `--corruption-max` (which are in percent, unlike these)
+    #[arg(long, value_name = "FRACTION,...", value_delimiter = ',', num_args = 1..,
+          conflicts_with_all = ["corruption_min", "corruption_max", "corruption_step", "corrupted_count", "corrupted_from"])]
+    corruption_list: Option<Vec<f64>>,
 
-    return 0;
+    /// Simulate exactly these numbers of corrupted cups, in the given order, instead of the range of `--corruption-min`
+    /// to `--corruption-max`; every result reports the fraction of the cups they are
+    #[arg(long, value_name = "COUNT,...", value_delimiter = ',', num_args = 1..,
+          conflicts_with_all = ["corruption_min", "corruption_max", "corruption_step", "corruption_list", "corrupted_count",
+                                "corrupted_from"])]
+    corrupted_count_list: Option<Vec<usize>>,
 }
 
-fn run_sim(fraction_corrupted_commitments: f64, corrupted_commitments: usize, target_eps: f64) {
-    let mut rng = thread_rng();
+#[derive(clap::Args, Debug)]
+struct SweepArgs {
+    #[command(flatten)]
+    options: Args,
+
+    #[command(flatten)]
+    thresholds: ThresholdArgs,
+
+    /// Sweep the shuffle size as well, from `min` to `max` (inclusive) in steps of `step`, for every corruption threshold
+    #[arg(long, value_name = "MIN:MAX:STEP", value_parser = parse_shuffle_size_sweep,
+          conflicts_with_all = ["shuffle_size", "dump_curve", "percentiles", "histogram", "dump_spread", "dump_differences"])]
+    shuffle_size_sweep: Option<ShuffleSizeSweep>,
+
+    /// Sweep the vector length as well, over these powers of two, for every shuffle size and corruption threshold.
+    /// The hiding threshold is derived anew for every length, unless `--target-eps` fixes it.
+    #[arg(long, value_name = "LENGTH,...", value_delimiter = ',', num_args = 1.., value_parser = parse_power_of_two,
+          conflicts_with_all = ["vector_length", "corrupted_count", "corrupted_count_list", "corrupted_from", "dump_curve", "percentiles", "histogram",
+                                "dump_spread", "dump_differences"])]
+    vector_length_sweep: Option<Vec<usize>>,
+
+    /// Simulate every threshold once, and write a result for each of these absolute hiding thresholds in (0, 1)
+    #[arg(long, value_name = "EPS,...", value_delimiter = ',', num_args = 1..,
+          conflicts_with_all = ["eps_numerator", "target_eps", "dry_run", "stream", "dump_curve",
+                                "dump_spread", "dump_differences", "percentiles", "histogram", "checkpoint", "summary"])]
+    eps_list: Option<Vec<f64>>,
+
+    /// Show a progress bar over the corruption thresholds (only when stdout is a terminal)
+    #[arg(long, conflicts_with = "quiet")]
+    progress: bool,
+
+    /// At the end of the sweep, write a table summarizing every threshold to stderr
+    #[arg(long)]
+    summary: bool,
+
+    /// Instead of the results, write the success probability of every round up to the completion round as CSV
+    #[arg(long, conflicts_with = "percentiles")]
+    dump_curve: bool,
+
+    /// Instead of the results, write the average number of cups above the target level in every round as CSV
+    #[arg(long, conflicts_with_all = ["dump_curve", "percentiles", "histogram"])]
+    dump_spread: bool,
+
+    /// Instead of the results, write the average largest difference between the water of two secrets in every round as CSV
+    #[arg(long, conflicts_with_all = ["dump_curve", "dump_spread", "percentiles", "histogram"])]
+    dump_differences: bool,
+
+    /// Write the resolved configuration of every threshold as JSON and exit, without running any simulation
+    #[arg(long, conflicts_with_all = ["dump_curve", "dump_spread", "dump_differences", "percentiles", "histogram", "checkpoint", "summary"])]
+    dry_run: bool,
+
+    /// Instead of the results, write the totals over all repetitions after every round as JSON lines, while the simulation runs
+    #[arg(long, conflicts_with_all = ["format", "dry_run", "dump_curve", "dump_spread", "dump_differences",
+                                      "percentiles", "histogram", "checkpoint", "summary"])]
+    stream: bool,
+
+    /// Instead of the results, write the 50th/90th/99th percentile of the per-repetition hiding rounds as CSV
+    #[arg(long)]
+    percentiles: bool,
+
+    /// Instead of the results, write a histogram of the per-repetition hiding rounds with buckets of this many rounds as CSV
+    #[arg(long, value_name = "BUCKET_SIZE", conflicts_with_all = ["dump_curve", "percentiles"])]
+    histogram: Option<usize>,
+
+    /// Also write the hiding round of every repetition of every threshold to this CSV file, -1 if it never hid the targets
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["dry_run", "stream", "eps_list"])]
+    dump_raw: Option<PathBuf>,
+
+    /// Append every finished threshold to this JSON-lines file, and skip thresholds it already holds.
+    /// Resuming needs the same arguments as the interrupted run, including the seed.
+    #[arg(long, value_name = "PATH", requires = "seed")]
+    checkpoint: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SingleArgs {
+    #[command(flatten)]
+    options: Args,
+
+    /// Corruption threshold to simulate, in percent
+    #[arg(long, value_name = "PERCENT", required_unless_present_any = ["corrupted_count", "corrupted_from"],
+          conflicts_with_all = ["corrupted_count", "corrupted_from"])]
+    corruption: Option<f64>,
+}
+
+#[derive(clap::Args, Debug)]
+struct TraceArgs {
+    #[command(flatten)]
+    options: Args,
+
+    #[command(flatten)]
+    thresholds: ThresholdArgs,
+
+    /// Write the state of the traced repetition after its last round to this file as JSON, to be continued by `--resume-state`
+    #[arg(long, value_name = "PATH")]
+    save_state: Option<PathBuf>,
+
+    /// Continue the repetition saved by `--save-state` in this file, with the same options (and seed), instead of tracing
+    /// one for every threshold; the continued rounds draw random choices of their own
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["corruption_min", "corruption_max", "corruption_step", "corruption_list",
+                                                         "corrupted_count_list"])]
+    resume_state: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompareArgs {
+    #[command(flatten)]
+    options: Args,
+
+    #[command(flatten)]
+    thresholds: ThresholdArgs,
+
+    /// Significance level of the two-sided rank-sum test of the hiding rounds of the two arms
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+
+    /// Options of arm B that differ from arm A, where `--<flag>=false` turns off a flag of arm A; every other option is
+    /// taken from arm A, including the seed
+    #[arg(value_name = "B_OPTIONS", required = true, last = true)]
+    overrides: Vec<OsString>,
+}
+
+#[derive(clap::Args, Debug)]
+struct FindMinArgs {
+    #[command(flatten)]
+    options: Args,
+
+    #[command(flatten)]
+    thresholds: ThresholdArgs,
+
+    /// Round budget of the search
+    #[arg(long, value_name = "ROUNDS", conflicts_with_all = ["shuffle_size", "max_shuffles"])]
+    deadline: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct CriticalArgs {
+    #[command(flatten)]
+    options: Args,
+
+    /// Stop once the critical corruption fraction is known to within this many percent
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.1)]
+    precision: f64,
+}
+
+/// Options shared by every subcommand: what to simulate, and how to write it
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// Number of cups (commitments) in the shuffled vector
+    #[arg(long, default_value_t = VECTOR_LENGTH)]
+    vector_length: usize,
+
+    /// Size of one local shuffle
+    #[arg(long, default_value_t = SHUFFLE_SIZE)]
+    shuffle_size: usize,
+
+    /// Size of every shuffle: `constant` (`--shuffle-size` cups), `uniform:<min>:<max>` or `poisson:<mean>`, drawn anew for each shuffle
+    #[arg(long, default_value = "constant", value_parser = parse_size_dist)]
+    shuffle_size_dist: SizeDist,
+
+    /// Upper bound on number of rounds in protocol execution, each of `--shuffles-per-round` shuffles
+    #[arg(long, default_value_t = MAX_SHUFFLES)]
+    max_shuffles: usize,
+
+    /// Number of back-to-back shuffles in a round, before the targets are checked for hiding
+    #[arg(long, default_value_t = 1)]
+    shuffles_per_round: usize,
+
+    /// Number of rounds at the start that only shuffle, without checking whether the targets are hidden;
+    /// success rounds still count from the first round
+    #[arg(long, value_name = "ROUNDS", default_value_t = 0)]
+    burn_in: usize,
+
+    /// Shuffle the same batches, drawn once from the seed, in every repetition, so that repetitions only differ in
+    /// which cups are corrupted; this reduces the variance of comparisons between corruption placements
+    #[arg(long)]
+    fixed_schedule: bool,
+
+    /// Number of repetitions over which the average benchmark outcomes are computed
+    #[arg(long, default_value_t = NUMBER_OF_REPETITIONS)]
+    repetitions: usize,
+
+    /// Simulate at most this many repetitions of a threshold at the same time, by default all of them. Chunks give the
+    /// same results, and chunks that are done early wait with their cups until the others are done too.
+    #[arg(long, value_name = "REPETITIONS")]
+    repetition_chunk: Option<usize>,
+
+    /// Cups whose hiding is tracked; a repetition succeeds once all of them are hidden
+    #[arg(long, value_delimiter = ',', default_value = "0")]
+    target_cups: Vec<usize>,
+
+    /// Track these cups as secrets that must become unlinkable: a repetition succeeds once, in every
+    /// cup, the water of any two secrets differs by less than the target level
+    #[arg(long, value_delimiter = ',', conflicts_with = "target_cups")]
+    secret_cups: Option<Vec<usize>>,
+
+    /// How the adversary picks which cups to corrupt
+    #[arg(long, value_enum, default_value_t = Adversary::StaticRandom)]
+    adversary: Adversary,
+
+    /// Start without corrupted cups and open this many new random cups every round, up to the corruption budget
+    #[arg(long, value_name = "RATE", conflicts_with = "adversary")]
+    opening_schedule: Option<usize>,
+
+    /// Start without corrupted cups and, after every round, corrupt the honest cup holding the most water, up to the corruption budget
+    #[arg(long, conflicts_with_all = ["adversary", "opening_schedule"])]
+    corrupt_max_each_round: bool,
+
+    /// Never corrupt a cup for good, but corrupt every cup of a batch for that shuffle only, with the corruption threshold as probability
+    #[arg(long, conflicts_with_all = ["adversary", "opening_schedule", "corrupt_max_each_round", "corrupted_from"])]
+    corruption_probability: bool,
+
+    /// Pour the water of cups opened by `--opening-schedule` into the honest cups of the next batch, instead of losing it
+    #[arg(long, requires = "opening_schedule")]
+    redistribute_opened_water: bool,
+
+    /// Number of rounds between reassignments of the adaptive adversary
+    #[arg(long, default_value_t = 100)]
+    adversary_interval: usize,
+
+    /// Fraction of the corruption budget the adaptive adversary reassigns at a time
+    #[arg(long, default_value_t = 0.1)]
+    adversary_portion: f64,
+
+    /// Let the adaptive adversary or `--corrupt-max-each-round` corrupt the target cups as well, or `--corrupted-from` include them
+    #[arg(long)]
+    allow_target_corruption: bool,
+
+    /// Open the target cups in this round, with `--open-target-probability`, and report the fraction of opened
+    /// repetitions that had already hidden them
+    #[arg(long, value_name = "ROUND")]
+    open_target_at: Option<usize>,
 
-    // Object for computing averages later on
-    let mut sum_succ_in_round: HashMap<usize, f64> = HashMap::new();
-    for t in 0..MAX_SHUFFLES { // Initialize hashmap with a default value of zero
-        sum_succ_in_round.insert(t, 0.0);
+    /// Probability with which the targets of a repetition are opened in the round of `--open-target-at`, in (0, 1]
+    #[arg(long, default_value_t = 1.0, requires = "open_target_at")]
+    open_target_probability: f64,
+
+    /// Corrupt the cups listed in this file (separated by whitespace or commas) in every repetition,
+    /// instead of sweeping over random corruption thresholds
+    #[arg(long, value_name = "PATH", conflicts_with = "opening_schedule")]
+    corrupted_from: Option<PathBuf>,
+
+    /// Initial water of every target: `single`, `uniform:<cups>` or `custom:<cup>=<water>,...`
+    #[arg(long, default_value = "single", value_parser = parse_initial_distribution)]
+    initial_distribution: InitialDistribution,
+
+    /// Weighting of the cups when picking a shuffle batch: `uniform`, `linear` or `geometric:<ratio>`
+    #[arg(long, default_value = "uniform", value_parser = parse_selection_weights)]
+    selection_weights: SelectionWeights,
+
+    /// How the cups of successive shuffle batches are chosen
+    #[arg(long, value_enum, default_value_t = Scheduler::RandomIid)]
+    batch_scheduler: Scheduler,
+
+    /// Numerator of the hiding threshold, which is this divided by the number of honest cups
+    #[arg(long, default_value_t = 4.0)]
+    eps_numerator: f64,
+
+    /// Use this absolute water level as hiding threshold for every corruption threshold, in (0, 1)
+    #[arg(long, conflicts_with = "eps_numerator")]
+    target_eps: Option<f64>,
+
+    /// Also count the targets as hidden with water this much above the hiding threshold, to forgive rounding residue;
+    /// unlike a larger threshold, it leaves the reported threshold and the model as they are
+    #[arg(long, default_value_t = 0.0)]
+    eps_tolerance: f64,
+
+    /// How far each shuffle of the mean rule moves the water of a cup towards the batch average, in (0, 1]
+    #[arg(long, default_value_t = 1.0)]
+    mixing_factor: f64,
+
+    /// Fraction of the water of every batch that the shuffler loses, in [0, 1); breaks conservation on purpose
+    #[arg(long, default_value_t = 0.0)]
+    leak_rate: f64,
+
+    /// Most water of a target a cup can hold after a shuffle; the rest spills and is lost, which breaks conservation on purpose
+    #[arg(long)]
+    cup_capacity: Option<f64>,
+
+    /// How a shuffle mixes the water of its batch: `full`, or `windowed:<cups>` to only mix within windows of adjacent cups
+    #[arg(long, default_value = "full", value_parser = parse_shuffle_model)]
+    shuffle_model: ShuffleModel,
+
+    /// How a shuffle redistributes the water of every group of honest cups of its batch
+    #[arg(long, value_enum, default_value_t = Mixing::Mean)]
+    mix_rule: Mixing,
+
+    /// Sum the water of a batch with Kahan summation, which is more accurate for large batches, but changes
+    /// fixed-seed results in their last digits
+    #[arg(long)]
+    kahan: bool,
+
+    /// Keep shuffling until the maximum number of shuffles, even once every repetition hid the targets
+    #[arg(long)]
+    no_early_exit: bool,
+
+    /// Only count a repetition as successful from the round on after which its targets stay hidden, and report how many
+    /// successes counting every round would have added
+    #[arg(long)]
+    stays_hidden: bool,
+
+    /// Also write the mean number of rounds in which the targets went from not hidden to hidden or back; far more than 1
+    /// means the water oscillates around the hiding threshold, and `--stays-hidden` is the sturdier success definition
+    #[arg(long)]
+    crossings: bool,
+
+    /// Fraction of repetitions that must have the targets hidden in a round for it to be the success round, in (0, 1]
+    #[arg(long, default_value_t = 1.0)]
+    success_probability: f64,
+
+    /// Seed for the random number generator (drawn from entropy if not given)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Random number generator of the repetitions; the same seed gives different results with different generators
+    #[arg(long, value_enum, default_value_t = Generator::Std)]
+    rng: Generator,
+
+    /// Output format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Write the results to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Corrupt exactly this many random cups, instead of sweeping over corruption thresholds
+    #[arg(long, value_name = "COUNT", conflicts_with = "corrupted_from")]
+    corrupted_count: Option<usize>,
+
+    /// How the number of corrupted cups varies between repetitions; results report the nominal fraction
+    #[arg(long, value_enum, default_value_t = Spread::Fixed, conflicts_with = "corrupted_from")]
+    corruption_spread: Spread,
+
+    /// Also report the highest water level in the success round, and its ratio to the target level
+    #[arg(long)]
+    eps_margin: bool,
+
+    /// Also report the first round in which 50%, 90%, 99% and all of the repetitions had the targets hidden
+    #[arg(long)]
+    probability_rounds: bool,
+
+    /// Also report a 95% confidence interval of the mean hiding round, bootstrapped from this many resamples
+    #[arg(long, value_name = "RESAMPLES")]
+    bootstrap: Option<usize>,
+
+    /// Also report the mean entropy and Gini coefficient of the water in the last simulated round
+    #[arg(long)]
+    concentration: bool,
+
+    /// Also report how many honest cups a shuffle mixed on average, which shrinks as more cups are corrupted
+    #[arg(long)]
+    honest_per_shuffle: bool,
+
+    /// Also report the largest drift of the total water of a target from its initial amount, after
+    /// accounting for water zeroed in corrupted cups or leaked, which is only rounding unless there is a bug
+    #[arg(long)]
+    mass_drift: bool,
+
+    /// Also report how long the simulation of every threshold took, in milliseconds (empty for checkpointed thresholds)
+    #[arg(long)]
+    timings: bool,
+
+    /// Do not write any diagnostics (warnings, logging) to stderr, only the data to stdout
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+/// Format an optional value, leaving it empty if missing so that it parses as a missing CSV value
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+/// Version of the document written by `--format json`. Adding fields keeps the version, while
+/// renaming, moving or removing any of them bumps it.
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// One threshold in the `results` of the JSON document. Merging the three objects gives the fields of
+/// a [`SimulationResult`], so that the record can be read back into one (numbers that are not finite
+/// are written as `null`, see there), which `tests/json_output.rs` checks.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    params: JsonParams,
+    summary: JsonSummary<'a>,
+    curve: JsonCurve<'a>,
+}
+
+/// What was simulated
+#[derive(Serialize)]
+struct JsonParams {
+    vector_length: usize,
+    shuffle_size: usize,
+    fraction_corrupted: f64,
+    corrupted_commitments: usize,
+    eps_numerator: Option<f64>,
+    target_eps: f64,
+}
+
+/// The outcome, as single values
+#[derive(Serialize)]
+struct JsonSummary<'a> {
+    success_round: Option<usize>,
+    probability_rounds: &'a [Option<usize>],
+    success_shuffles: Option<usize>,
+    final_success_probability: f64,
+    transient_successes: u64,
+    mean_crossings: f64,
+    hiding_round_stats: Option<RoundStats>,
+    max_water_at_success: Option<f64>,
+    eps_ratio: Option<f64>,
+    entropy: f64,
+    gini: f64,
+    mean_honest_per_shuffle: f64,
+    max_mass_drift: Option<f64>,
+    targets_opened: u64,
+    safe_when_opened: u64,
+    mean_round_interval: Option<(f64, f64)>,
+}
+
+/// The outcome, round by round (and repetition by repetition for `hiding_rounds`)
+#[derive(Serialize)]
+struct JsonCurve<'a> {
+    successes_in_round: &'a [u64],
+    cups_above_eps_in_round: &'a [u64],
+    secret_difference_in_round: &'a [f64],
+    hiding_rounds: &'a [Option<usize>],
+}
+
+impl<'a> From<&'a SimulationResult> for JsonRecord<'a> {
+    fn from(result: &'a SimulationResult) -> Self {
+        JsonRecord {
+            params: JsonParams {
+                vector_length: result.vector_length,
+                shuffle_size: result.shuffle_size,
+                fraction_corrupted: result.fraction_corrupted,
+                corrupted_commitments: result.corrupted_commitments,
+                eps_numerator: result.eps_numerator,
+                target_eps: result.target_eps,
+            },
+            summary: JsonSummary {
+                success_round: result.success_round,
+                probability_rounds: &result.probability_rounds,
+                success_shuffles: result.success_shuffles,
+                final_success_probability: result.final_success_probability,
+                transient_successes: result.transient_successes,
+                mean_crossings: result.mean_crossings,
+                hiding_round_stats: result.hiding_round_stats,
+                max_water_at_success: result.max_water_at_success,
+                eps_ratio: result.eps_ratio,
+                entropy: result.entropy,
+                gini: result.gini,
+                mean_honest_per_shuffle: result.mean_honest_per_shuffle,
+                max_mass_drift: result.max_mass_drift,
+                targets_opened: result.targets_opened,
+                safe_when_opened: result.safe_when_opened,
+                mean_round_interval: result.mean_round_interval,
+            },
+            curve: JsonCurve {
+                successes_in_round: &result.successes_in_round,
+                cups_above_eps_in_round: &result.cups_above_eps_in_round,
+                secret_difference_in_round: &result.secret_difference_in_round,
+                hiding_rounds: &result.hiding_rounds,
+            },
+        }
     }
+}
 
-//    println!("\t{NUMBER_OF_REPETITIONS} Repetitions:");
-//    println!("\t------------");
+/// Write the preamble of the results, if the format has one, for a sweep over `thresholds`
+fn write_header(out: &mut dyn Write, args: &Args, seed: u64, thresholds: &[f64]) -> io::Result<()> {
+    match args.format {
+        OutputFormat::Text | OutputFormat::Legacy => Ok(()),
+        // The column labels, below the label of the row labels
+        OutputFormat::Matrix => {
+            let columns: Vec<String> = thresholds.iter().map(f64::to_string).collect();
+            writeln!(out, "shuffle_size,{}", columns.join(","))
+        }
+        OutputFormat::Csv => {
+            let mut columns = vec!["vector_length", "shuffle_size", "fraction_corrupted", "corrupted_commitments", "eps_numerator", "target_eps", "success_round",
+                                   "mean_round", "variance_round", "std_dev_round", "final_probability", "success_shuffles"];
+            let probability_columns: Vec<String> = PROBABILITY_LEVELS.iter().map(|level| format!("p{}_round", level * 100.0)).collect();
+            if args.eps_margin {
+                columns.extend(["max_water", "eps_ratio"]);
+            }
+            if args.probability_rounds {
+                columns.extend(probability_columns.iter().map(String::as_str));
+            }
+            if args.concentration {
+                columns.extend(["entropy", "gini"]);
+            }
+            if args.honest_per_shuffle {
+                columns.push("mean_honest_per_shuffle");
+            }
+            if args.stays_hidden {
+                columns.push("transient_successes");
+            }
+            if args.crossings {
+                columns.push("mean_crossings");
+            }
+            if args.mass_drift {
+                columns.push("max_mass_drift");
+            }
+            if args.open_target_at.is_some() {
+                columns.extend(["targets_opened", "safe_fraction"]);
+            }
+            if args.bootstrap.is_some() {
+                columns.extend(["mean_round_ci_low", "mean_round_ci_high"]);
+            }
+            if args.timings {
+                columns.push("elapsed_ms");
+            }
+            writeln!(out, "{}", columns.join(","))
+        }
+        // The metadata comes first, so that the results can be streamed after it
+        OutputFormat::Json => {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+            writeln!(out, "{{\"schema_version\":{JSON_SCHEMA_VERSION},\"crate_version\":\"{}\",\"seed\":{seed},\"timestamp\":{timestamp},\"results\":[",
+                     env!("CARGO_PKG_VERSION"))
+        }
+    }
+}
 
-    for _ in 0..NUMBER_OF_REPETITIONS {
-//        println!("\tRepetition {}/{}", repetition+1, NUMBER_OF_REPETITIONS);
+/// Write the closing part of the results, if the format has one
+fn write_footer(out: &mut dyn Write, args: &Args) -> io::Result<()> {
+    match args.format {
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Matrix | OutputFormat::Legacy => Ok(()),
+        OutputFormat::Json => writeln!(out, "\n]}}"),
+    }
+}
 
-        // Flag to be set, when sufficient shuffling was successfully done in this repetition
-        let mut is_success = false;
+/// Write the outcome of the simulation of a single corruption threshold, the `index`-th of a sweep
+/// over `thresholds` (for every shuffle size), which took `elapsed` to simulate unless it was read
+/// from a checkpoint
+fn write_result(out: &mut dyn Write, args: &Args, seed: u64, index: usize, thresholds: &[f64], result: &SimulationResult,
+                elapsed: Option<Duration>) -> io::Result<()> {
+    let SimulationResult { vector_length, shuffle_size, fraction_corrupted: fraction_corrupted_commitments, corrupted_commitments, eps_numerator, target_eps,
+                           success_round: successful_round, .. } = result;
+    match args.format {
+        OutputFormat::Text => {
+            let eps = eps_numerator.map_or("fixed eps".to_string(), |numerator| format!("eps numerator {numerator}"));
+            let round = successful_round.map_or_else(|| format!("NEVER (hidden with probability {} in the last round)",
+                                                               result.final_success_probability), |r| r.to_string());
+            // Rounds and shuffles only differ with several shuffles per round
+            let round = match result.success_shuffles {
+                Some(shuffles) if args.shuffles_per_round > 1 => format!("{round} ({shuffles} shuffles)"),
+                _ => round,
+            };
+            let stats = result.hiding_round_stats.map_or(String::new(), |stats| {
+                format!(" [mean {:.1} var {:.1} sd {:.1}]", stats.mean, stats.variance, stats.std_dev)
+            });
+            // With the targets opened, whether they were hidden by then is what matters most
+            let safe = match args.open_target_at {
+                Some(at) => format!("safe {} of {} opened at round {at}, hidden at ", optional(result.safe_fraction()), result.targets_opened),
+                None => String::new(),
+            };
+            let mut line = format!("Simulation parameters: [{vector_length} {shuffle_size}] [{fraction_corrupted_commitments} {target_eps}] \
+                                    [corrupted {corrupted_commitments}] [{eps}] [seed {seed}]: {safe}{round}{stats}");
+            if let (true, Some(max_water), Some(ratio)) = (args.eps_margin, result.max_water_at_success, result.eps_ratio) {
+                line += &format!(" [max water {max_water} ratio {ratio:.3}]");
+            }
+            if args.probability_rounds {
+                let rounds: Vec<String> = PROBABILITY_LEVELS.iter().zip(&result.probability_rounds)
+                    .map(|(level, round)| format!("p{} {}", level * 100.0, round.map_or("NEVER".to_string(), |round| round.to_string())))
+                    .collect();
+                line += &format!(" [{}]", rounds.join(" "));
+            }
+            if args.concentration {
+                line += &format!(" [entropy {:.3} gini {:.3}]", result.entropy, result.gini);
+            }
+            if args.honest_per_shuffle {
+                line += &format!(" [honest per shuffle {:.2}]", result.mean_honest_per_shuffle);
+            }
+            if args.stays_hidden {
+                line += &format!(" [transient successes {}]", result.transient_successes);
+            }
+            if args.crossings {
+                line += &format!(" [crossings {:.2}]", result.mean_crossings);
+            }
+            if let (true, Some(drift)) = (args.mass_drift, result.max_mass_drift) {
+                line += &format!(" [mass drift {drift:.1e}]");
+            }
+            if let (Some(_), Some((low, high))) = (args.bootstrap, result.mean_round_interval) {
+                line += &format!(" [mean 95% CI {low:.1}-{high:.1}]");
+            }
+            if let (true, Some(elapsed)) = (args.timings, elapsed) {
+                line += &format!(" [elapsed {} ms]", elapsed.as_millis());
+            }
+            writeln!(out, "{line}")
+        }
+        // Leave fields empty when the cup was never hidden, so they parse as missing values
+        OutputFormat::Csv => {
+            let stats = result.hiding_round_stats;
+            let mut fields = vec![vector_length.to_string(), shuffle_size.to_string(), fraction_corrupted_commitments.to_string(),
+                                  corrupted_commitments.to_string(), optional(*eps_numerator), target_eps.to_string(),
+                                  optional(*successful_round), optional(stats.map(|stats| stats.mean)),
+                                  optional(stats.map(|stats| stats.variance)), optional(stats.map(|stats| stats.std_dev)),
+                                  result.final_success_probability.to_string(), optional(result.success_shuffles)];
+            if args.eps_margin {
+                fields.extend([optional(result.max_water_at_success), optional(result.eps_ratio)]);
+            }
+            if args.probability_rounds {
+                fields.extend(result.probability_rounds.iter().map(|&round| optional(round)));
+            }
+            if args.concentration {
+                fields.extend([result.entropy.to_string(), result.gini.to_string()]);
+            }
+            if args.honest_per_shuffle {
+                fields.push(result.mean_honest_per_shuffle.to_string());
+            }
+            if args.stays_hidden {
+                fields.push(result.transient_successes.to_string());
+            }
+            if args.crossings {
+                fields.push(result.mean_crossings.to_string());
+            }
+            if args.mass_drift {
+                fields.push(optional(result.max_mass_drift));
+            }
+            if args.open_target_at.is_some() {
+                fields.extend([result.targets_opened.to_string(), optional(result.safe_fraction())]);
+            }
+            if args.bootstrap.is_some() {
+                let interval = result.mean_round_interval;
+                fields.extend([optional(interval.map(|(low, _)| low)), optional(interval.map(|(_, high)| high))]);
+            }
+            if args.timings {
+                fields.push(optional(elapsed.map(|elapsed| elapsed.as_millis())));
+            }
+            writeln!(out, "{}", fields.join(","))
+        }
+        // The sweep runs over thresholds for one shuffle size after the other, so rows are filled in order
+        OutputFormat::Matrix => {
+            let column = index % thresholds.len();
+            if column == 0 {
+                write!(out, "{shuffle_size}")?;
+            }
+            write!(out, ",{}", optional(*successful_round))?;
+            if column == thresholds.len() - 1 {
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        // Nothing but the parameters and the round, which is how the original simulation reported `NEVER`
+        OutputFormat::Legacy => {
+            writeln!(out, "Simulation parameters: [{vector_length} {shuffle_size}] [{fraction_corrupted_commitments} {target_eps}]: {}",
+                     successful_round.unwrap_or(0))
+        }
+        // Separate the array elements without leaving a trailing comma after the last one
+        OutputFormat::Json => {
+            if index > 0 {
+                writeln!(out, ",")?;
+            }
+            serde_json::to_writer(&mut *out, &JsonRecord::from(result))?;
+            Ok(())
+        }
+    }
+}
 
-        // Select random subset of commitments to be corrupt (do not corrupt indx 0)
-        let bad_commitment_indices = HashSet::from_iter((1..VECTOR_LENGTH).choose_multiple(&mut rng, corrupted_commitments));
+/// Write the success probability of every round up to the success round (or all rounds if never hidden)
+fn write_curve(out: &mut dyn Write, repetitions: usize, result: &SimulationResult) -> io::Result<()> {
+    let last_round = result.success_round.unwrap_or(result.successes_in_round.len());
+    for (t, successes) in result.successes_in_round[..last_round].iter().enumerate() {
+        writeln!(out, "{},{},{}", result.fraction_corrupted, t+1, *successes as f64 / repetitions as f64)?;
+    }
+    Ok(())
+}
 
-        // Initially all cups have 0 water apart for the one cup we care about tracking
-        let mut water_cups: HashMap<usize, f64> = HashMap::new();
-        let target_cup_indx = 0; // Just pick the first cup and track that
-        water_cups.insert(target_cup_indx, 1.0);
+/// Write the average number of cups above `target_eps` in every round up to the success round (or all rounds if never hidden)
+fn write_spread(out: &mut dyn Write, repetitions: usize, result: &SimulationResult) -> io::Result<()> {
+    let last_round = result.success_round.unwrap_or(result.cups_above_eps_in_round.len());
+    for (t, cups) in result.cups_above_eps_in_round[..last_round].iter().enumerate() {
+        writeln!(out, "{},{},{}", result.fraction_corrupted, t+1, *cups as f64 / repetitions as f64)?;
+    }
+    Ok(())
+}
 
-        // Do all the shuffles
-        for t in 0..MAX_SHUFFLES {
-            // if t % 500 == 0 {
-            //  println!("\tRound {}", t);
-            //}
+/// Write the average largest difference between the secrets in every round up to the success round (or all rounds if never hidden)
+fn write_differences(out: &mut dyn Write, result: &SimulationResult) -> io::Result<()> {
+    let last_round = result.success_round.unwrap_or(result.secret_difference_in_round.len());
+    for (t, difference) in result.secret_difference_in_round[..last_round].iter().enumerate() {
+        writeln!(out, "{},{},{difference}", result.fraction_corrupted, t+1)?;
+    }
+    Ok(())
+}
 
-            // Each shuffler distributes the water to all the cups
-            distribute_water(&mut water_cups, &bad_commitment_indices, &mut rng);
+/// Parse the `--initial-distribution` argument
+fn parse_initial_distribution(arg: &str) -> Result<InitialDistribution, String> {
+    let (kind, params) = arg.split_once(':').unwrap_or((arg, ""));
+    match kind {
+        "single" => Ok(InitialDistribution::SinglePoint),
+        "uniform" => {
+            let count: usize = params.parse().map_err(|_| format!("invalid number of cups `{params}`"))?;
+            if count == 0 {
+                return Err("water must be split across at least one cup".to_string());
+            }
+            Ok(InitialDistribution::Uniform(count))
+        }
+        // The amounts of water are checked by `SimulationConfig::validate`
+        "custom" => {
+            let water = params.split(',').map(|entry| {
+                let (cup, amount) = entry.split_once('=').ok_or(format!("expected `<cup>=<water>`, got `{entry}`"))?;
+                let cup: usize = cup.parse().map_err(|_| format!("invalid cup `{cup}`"))?;
+                let amount: f64 = amount.parse().map_err(|_| format!("invalid amount of water `{amount}`"))?;
+                Ok((cup, amount))
+            });
+            Ok(InitialDistribution::Custom(water.collect::<Result<_, String>>()?))
+        }
+        _ => Err(format!("unknown distribution `{kind}`, expected `single`, `uniform` or `custom`")),
+    }
+}
+
+/// Shuffle sizes from `min` to `max` (inclusive) in steps of `step`
+#[derive(Clone, Copy, Debug)]
+struct ShuffleSizeSweep {
+    min: usize,
+    max: usize,
+    step: usize,
+}
+
+/// Parse the `--shuffle-size-sweep` argument
+fn parse_shuffle_size_sweep(arg: &str) -> Result<ShuffleSizeSweep, String> {
+    let sizes: Vec<usize> = arg.split(':')
+        .map(|size| size.parse().map_err(|_| format!("invalid shuffle size `{size}`")))
+        .collect::<Result<_, String>>()?;
+    let [min, max, step] = sizes[..] else {
+        return Err(format!("expected `<min>:<max>:<step>`, got `{arg}`"));
+    };
+    if min == 0 || step == 0 {
+        return Err("shuffle sizes and step must be at least 1".to_string());
+    }
+    if min > max {
+        return Err(format!("minimum shuffle size ({min}) cannot exceed maximum shuffle size ({max})"));
+    }
+    Ok(ShuffleSizeSweep { min, max, step })
+}
+
+/// Parse a vector length of `--vector-length-sweep`
+fn parse_power_of_two(arg: &str) -> Result<usize, String> {
+    let length: usize = arg.parse().map_err(|_| format!("invalid vector length `{arg}`"))?;
+    if !length.is_power_of_two() {
+        return Err(format!("vector length ({length}) must be a power of two"));
+    }
+    Ok(length)
+}
+
+/// Parse the `--shuffle-model` argument
+fn parse_shuffle_model(arg: &str) -> Result<ShuffleModel, String> {
+    let (kind, params) = arg.split_once(':').unwrap_or((arg, ""));
+    match kind {
+        "full" => Ok(ShuffleModel::FullAverage),
+        "windowed" => {
+            let window: usize = params.parse().map_err(|_| format!("invalid window `{params}`"))?;
+            if window == 0 {
+                return Err("window must hold at least one cup".to_string());
+            }
+            Ok(ShuffleModel::WindowedAverage { window })
+        }
+        _ => Err(format!("unknown shuffle model `{kind}`, expected `full` or `windowed`")),
+    }
+}
+
+/// Parse the `--shuffle-size-dist` argument
+fn parse_size_dist(arg: &str) -> Result<SizeDist, String> {
+    let (kind, params) = arg.split_once(':').unwrap_or((arg, ""));
+    match kind {
+        "constant" => Ok(SizeDist::Constant),
+        "uniform" => {
+            let (min, max) = params.split_once(':').ok_or(format!("expected `uniform:<min>:<max>`, got `{arg}`"))?;
+            let min: usize = min.parse().map_err(|_| format!("invalid minimum shuffle size `{min}`"))?;
+            let max: usize = max.parse().map_err(|_| format!("invalid maximum shuffle size `{max}`"))?;
+            if min == 0 || min > max {
+                return Err("shuffle sizes must be a non-empty range from at least 1".to_string());
+            }
+            Ok(SizeDist::Uniform { min, max })
+        }
+        "poisson" => {
+            let mean: f64 = params.parse().map_err(|_| format!("invalid mean shuffle size `{params}`"))?;
+            if !(mean > 0.0 && mean.is_finite()) {
+                return Err("mean shuffle size must be positive".to_string());
+            }
+            Ok(SizeDist::Poisson { mean })
+        }
+        _ => Err(format!("unknown shuffle size distribution `{kind}`, expected `constant`, `uniform` or `poisson`")),
+    }
+}
+
+/// Parse the `--selection-weights` argument
+fn parse_selection_weights(arg: &str) -> Result<SelectionWeights, String> {
+    let (kind, params) = arg.split_once(':').unwrap_or((arg, ""));
+    match kind {
+        "uniform" => Ok(SelectionWeights::Uniform),
+        "linear" => Ok(SelectionWeights::Linear),
+        "geometric" => {
+            let ratio: f64 = params.parse().map_err(|_| format!("invalid ratio `{params}`"))?;
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                return Err("geometric ratio must be in (0, 1]".to_string());
+            }
+            Ok(SelectionWeights::Geometric { ratio })
+        }
+        _ => Err(format!("unknown weighting `{kind}`, expected `uniform`, `linear` or `geometric`")),
+    }
+}
+
+/// Everything that is derived for one threshold before it is simulated, as written by `--dry-run`
+#[derive(Serialize)]
+struct ResolvedThreshold {
+    fraction_corrupted: f64,
+    corrupted_commitments: usize,
+    target_eps: f64,
+    config: SimulationConfig,
+}
+
+/// One round of one threshold, as a line written by `--stream`
+#[derive(Serialize)]
+struct StreamRecord {
+    shuffle_size: usize,
+    fraction_corrupted: f64,
+    #[serde(flatten)]
+    update: RoundUpdate,
+}
+
+/// Simulate a threshold with `R`, and write the totals of every round as a JSON line as soon as it is done
+fn write_stream<R: Rng + SeedableRng + Send + Sync>(out: &mut dyn Write, config: &SimulationConfig, fraction_corrupted_commitments: f64)
+    -> io::Result<()> {
+    let stream = run_sim_streaming_with_rng::<R>(config, fraction_corrupted_commitments)
+        .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+    for update in stream {
+        let update = update.unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+        let record = StreamRecord { shuffle_size: config.shuffle_size, fraction_corrupted: fraction_corrupted_commitments, update };
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Results of a checkpoint file, keyed by vector length, shuffle size and the bits of the corruption threshold
+type Checkpointed = HashMap<(usize, usize, u64), SimulationResult>;
+
+/// Read the results of a checkpoint file.
+///
+/// Lines that do not parse, such as one cut off by a killed run, are skipped with a warning. If the
+/// file exists, it is returned opened for appending, and ends in a newline.
+fn load_checkpoint(path: &Path) -> io::Result<(Checkpointed, File)> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+    let mut results = HashMap::new();
+    for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+        match serde_json::from_str::<SimulationResult>(line) {
+            Ok(result) => {
+                results.insert((result.vector_length, result.shuffle_size, result.fraction_corrupted.to_bits()), result);
+            }
+            Err(error) => warn!("skipping line {} of checkpoint {}: {error}", number + 1, path.display()),
+        }
+    }
+
+    let mut file = File::options().create(true).append(true).open(path)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        writeln!(file)?;
+    }
+    Ok((results, file))
+}
+
+/// Read the cups listed in a `--corrupted-from` file, sorted and without duplicates
+fn read_corrupted_cups(path: &Path) -> Result<Vec<usize>, String> {
+    let contents = fs::read_to_string(path).map_err(|error| format!("cannot read {}: {error}", path.display()))?;
+    let mut cups = contents.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse().map_err(|_| format!("invalid cup `{entry}` in {}", path.display())))
+        .collect::<Result<Vec<usize>, String>>()?;
+    cups.sort_unstable();
+    cups.dedup();
+    Ok(cups)
+}
+
+/// Exit with a usage error about an invalid combination of arguments
+fn validation_error(message: impl std::fmt::Display) -> ! {
+    cli_command().error(ErrorKind::ValueValidation, message).exit()
+}
+
+/// Set by the first Ctrl-C, after which no new thresholds are started
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Exit code of a sweep that was interrupted by Ctrl-C, as for a shell killed by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Report a threshold whose simulation failed, and exit
+fn simulation_error(fraction_corrupted_commitments: f64, error: SimError) -> ! {
+    eprintln!("error: fraction {fraction_corrupted_commitments}: {error}");
+    process::exit(1)
+}
+
+/// Return the corruption fractions of a sweep from `min` to `max` percent (inclusive) in steps of `step` percent
+fn corruption_thresholds(min: f64, max: f64, step: f64) -> Vec<f64> {
+    // Allow for rounding error in the number of steps, so that `max` itself is part of the sweep
+    let steps = ((max - min) / step + 1e-9).floor() as usize;
+    (0..=steps)
+        .map(|i| {
+            // Round off the error accumulated in `min + i*step`, so that e.g. 33.3% is reported as 0.333
+            let percent = min + i as f64 * step;
+            (percent * 1e7).round() / 1e9
+        })
+        .collect()
+}
+
+/// Return the fraction of `count` corrupted cups out of `vector_length`, the smallest one of which
+/// [`threshold_parameters`] corrupts exactly `count` cups
+fn fraction_of_count(count: usize, vector_length: usize) -> f64 {
+    let fraction = count as f64 / vector_length as f64;
+    // The corrupted cups are rounded down from `fraction * vector_length`, which is just below `count` if the division rounded down
+    if ((vector_length as f64 * fraction) as usize) < count {
+        fraction.next_up()
+    } else {
+        fraction
+    }
+}
+
+/// Return the fraction of cups that `--corrupted-from` or `--corrupted-count` corrupt, if either was given
+fn fixed_threshold(config: &SimulationConfig) -> Option<f64> {
+    let count = config.corrupted_cups.as_ref().map(Vec::len).or(config.corrupted_count)?;
+    Some(count as f64 / config.vector_length as f64)
+}
+
+impl ThresholdArgs {
+    /// Validate the sweep, exiting with a usage error if it is empty, and return its corruption fractions (or those
+    /// of `--corruption-list` or `--corrupted-count-list`), or the single one of `--corrupted-from` or `--corrupted-count`
+    fn fractions(&self, config: &SimulationConfig) -> Vec<f64> {
+        if let Some(fraction) = fixed_threshold(config) {
+            return vec![fraction];
+        }
+        if let Some(list) = &self.corruption_list {
+            if let Some(fraction) = list.iter().find(|fraction| !(0.0..1.0).contains(*fraction)) {
+                validation_error(format!("corrupted fraction ({fraction}) of --corruption-list must be at least 0 and below 1"));
+            }
+            if let Some((index, fraction)) = list.iter().enumerate().find(|(index, fraction)| list[..*index].contains(fraction)) {
+                validation_error(format!("corrupted fraction {fraction} is listed twice in --corruption-list (at positions {} and {})",
+                                         list.iter().position(|other| other == fraction).unwrap() + 1, index + 1));
+            }
+            return list.clone();
+        }
+        if let Some(counts) = &self.corrupted_count_list {
+            if let Some(count) = counts.iter().find(|&&count| count >= config.vector_length) {
+                validation_error(format!("corrupted count ({count}) of --corrupted-count-list must be below the vector length ({})",
+                                         config.vector_length));
+            }
+            if let Some((index, count)) = counts.iter().enumerate().find(|(index, count)| counts[..*index].contains(count)) {
+                validation_error(format!("corrupted count {count} is listed twice in --corrupted-count-list (at positions {} and {})",
+                                         counts.iter().position(|other| other == count).unwrap() + 1, index + 1));
+            }
+            return counts.iter().map(|&count| fraction_of_count(count, config.vector_length)).collect();
+        }
+        if !(0.0..100.0).contains(&self.corruption_min) || !(0.0..100.0).contains(&self.corruption_max) {
+            validation_error("corruption thresholds must be at least 0% and below 100%");
+        }
+        if self.corruption_min > self.corruption_max {
+            validation_error(format!("corruption minimum ({}%) cannot exceed corruption maximum ({}%)",
+                                     self.corruption_min, self.corruption_max));
+        }
+        if self.corruption_step <= 0.0 {
+            validation_error("corruption step must be positive");
+        }
+        corruption_thresholds(self.corruption_min, self.corruption_max, self.corruption_step)
+    }
+}
+
+/// Write a table with a row per threshold (and shuffle size, if those were swept as well)
+fn write_summary(out: &mut dyn Write, results: &[SimulationResult], with_vector_length: bool, with_shuffle_size: bool) -> io::Result<()> {
+    let vector_length_column = |value: &str| if with_vector_length { format!("{value:>13} | ") } else { String::new() };
+    let shuffle_size_column = |value: &str| if with_shuffle_size { format!("{value:>12} | ") } else { String::new() };
+    writeln!(out, "{}{}{:>8} | {:>13} | {:>8} | {:>5} | {:>5}", vector_length_column("vector_length"), shuffle_size_column("shuffle_size"),
+             "fraction", "success_round", "mean", "p90", "never")?;
+    for result in results {
+        let round = result.success_round.map_or("NEVER".to_string(), |round| round.to_string());
+        let mean = result.hiding_round_stats.map_or("-".to_string(), |stats| format!("{:.1}", stats.mean));
+        let p90 = result.hiding_round_percentile(90.0).map_or("-".to_string(), |round| round.to_string());
+        let never = result.hiding_rounds.iter().filter(|round| round.is_none()).count();
+        writeln!(out, "{}{}{:>8} | {round:>13} | {mean:>8} | {p90:>5} | {never:>5}", vector_length_column(&result.vector_length.to_string()),
+                 shuffle_size_column(&result.shuffle_size.to_string()), result.fraction_corrupted)?;
+    }
+    Ok(())
+}
+
+/// Percentiles of the per-repetition hiding rounds written by `--percentiles`
+const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// Write the percentiles of the hiding rounds, leaving the field empty if the percentile never hid
+fn write_percentiles(out: &mut dyn Write, result: &SimulationResult) -> io::Result<()> {
+    write!(out, "{}", result.fraction_corrupted)?;
+    for p in PERCENTILES {
+        let round = result.hiding_round_percentile(p);
+        write!(out, ",{}", optional(round))?;
+    }
+    writeln!(out)
+}
+
+/// Write the number of repetitions hiding the targets in every bucket of `bucket_size` rounds,
+/// skipping empty buckets, followed by the number of repetitions that never did
+fn write_histogram(out: &mut dyn Write, bucket_size: usize, result: &SimulationResult) -> io::Result<()> {
+    let mut buckets: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut never = 0;
+    for round in &result.hiding_rounds {
+        match round {
+            Some(round) => *buckets.entry(round / bucket_size * bucket_size).or_default() += 1,
+            None => never += 1,
+        }
+    }
+    for (bucket_start, count) in buckets {
+        writeln!(out, "{},{bucket_start},{count}", result.fraction_corrupted)?;
+    }
+    writeln!(out, "{},never,{never}", result.fraction_corrupted)
+}
+
+/// Write the hiding round of every repetition, or -1 for repetitions that never hid the targets
+fn write_raw(out: &mut dyn Write, result: &SimulationResult) -> io::Result<()> {
+    for (repetition, round) in result.hiding_rounds.iter().enumerate() {
+        match round {
+            Some(round) => writeln!(out, "{},{repetition},{round}", result.fraction_corrupted)?,
+            None => writeln!(out, "{},{repetition},-1", result.fraction_corrupted)?,
+        }
+    }
+    Ok(())
+}
+
+/// Validate the arguments, exiting with a usage error if they cannot be simulated, and return the
+/// configuration they describe, before it is specialized to a shuffle size and threshold
+fn simulation_config(args: &Args, seed: u64) -> SimulationConfig {
+    if args.shuffle_size > args.vector_length {
+        validation_error(format!("shuffle size ({}) cannot exceed vector length ({})", args.shuffle_size, args.vector_length));
+    }
+    // Secrets are targets as well, they only succeed differently
+    let target_cups = args.secret_cups.clone().unwrap_or_else(|| args.target_cups.clone());
+    if let Some(target) = target_cups.iter().find(|&&target| target >= args.vector_length) {
+        validation_error(format!("target cup ({target}) must be smaller than vector length ({})", args.vector_length));
+    }
+
+    if args.adversary_interval == 0 {
+        validation_error("adversary interval must be at least 1");
+    }
+    if !(0.0..=1.0).contains(&args.adversary_portion) {
+        validation_error("adversary portion must be between 0 and 1");
+    }
+    if let InitialDistribution::Custom(water) = &args.initial_distribution {
+        if let Some((cup, _)) = water.iter().find(|(cup, _)| *cup >= args.vector_length) {
+            validation_error(format!("initial water cup ({cup}) must be smaller than vector length ({})", args.vector_length));
+        }
+    }
+    let corrupted_cups = args.corrupted_from.as_deref().map(|path| {
+        let cups = read_corrupted_cups(path).unwrap_or_else(|message| validation_error(message));
+        if let Some(cup) = cups.iter().find(|&&cup| cup >= args.vector_length) {
+            validation_error(format!("corrupted cup ({cup}) must be smaller than vector length ({})", args.vector_length));
+        }
+        if let Some(target) = cups.iter().find(|&cup| target_cups.contains(cup) && !args.allow_target_corruption) {
+            validation_error(format!("corrupted cup ({target}) is a target cup; pass --allow-target-corruption to allow this"));
+        }
+        cups
+    });
+    if !(args.eps_numerator > 0.0 && args.eps_numerator.is_finite()) {
+        validation_error("eps numerator must be positive");
+    }
+    if !(args.mixing_factor > 0.0 && args.mixing_factor <= 1.0) {
+        validation_error("mixing factor must be in (0, 1]");
+    }
+    if args.bootstrap == Some(0) {
+        validation_error("bootstrap resamples must be at least 1");
+    }
+
+    let adversary = match (args.opening_schedule, args.adversary) {
+        _ if args.corrupt_max_each_round => AdversaryStrategy::CorruptMaxEachRound,
+        _ if args.corruption_probability => AdversaryStrategy::Transient,
+        (Some(rate), _) => AdversaryStrategy::ProgressiveOpening { rate, redistribute: args.redistribute_opened_water },
+        (None, Adversary::StaticRandom) => AdversaryStrategy::StaticRandom,
+        (None, Adversary::AdaptiveGreedy) => AdversaryStrategy::AdaptiveGreedy {
+            interval: args.adversary_interval,
+            portion: args.adversary_portion,
+        },
+    };
 
-            // Check whether target commitment is hidden sufficiently well
-            let max_water = water_cups.values().max_by(|a, b| a.total_cmp(b)).unwrap();
-            if *max_water < target_eps {
-                let successes = sum_succ_in_round.entry(t).or_insert(0.0);
-                *successes += 1.0;
+    let config = SimulationConfig {
+        vector_length: args.vector_length,
+        shuffle_size: args.shuffle_size,
+        shuffle_size_dist: args.shuffle_size_dist,
+        max_shuffles: args.max_shuffles,
+        shuffles_per_round: args.shuffles_per_round,
+        burn_in: args.burn_in,
+        fixed_schedule: args.fixed_schedule,
+        repetitions: args.repetitions,
+        repetition_chunk: args.repetition_chunk,
+        seed,
+        target_cups,
+        success_criterion: match args.secret_cups {
+            Some(_) => SuccessCriterion::Unlinkable,
+            None => SuccessCriterion::Hidden,
+        },
+        success_counting: if args.stays_hidden { SuccessCounting::StaysHidden } else { SuccessCounting::PerRound },
+        adversary,
+        allow_target_corruption: args.allow_target_corruption,
+        target_opening: args.open_target_at.map(|round| TargetOpening { round, probability: args.open_target_probability }),
+        mixing_factor: args.mixing_factor,
+        leak_rate: args.leak_rate,
+        cup_capacity: args.cup_capacity,
+        shuffle_model: args.shuffle_model,
+        mix_rule: match args.mix_rule {
+            Mixing::Mean => MixRuleKind::Mean,
+            Mixing::PairwiseSwap => MixRuleKind::PairwiseSwap,
+        },
+        summation: if args.kahan { Summation::Kahan } else { Summation::Naive },
+        early_exit: !args.no_early_exit,
+        success_probability: args.success_probability,
+        initial_distribution: args.initial_distribution.clone(),
+        eps_numerator: args.eps_numerator,
+        target_eps: args.target_eps,
+        eps_tolerance: args.eps_tolerance,
+        selection_weights: args.selection_weights,
+        batch_scheduler: match args.batch_scheduler {
+            Scheduler::RandomIid => BatchScheduler::RandomIid,
+            Scheduler::RoundRobinDisjoint => BatchScheduler::RoundRobinDisjoint,
+        },
+        corrupted_cups,
+        corrupted_count: args.corrupted_count,
+        corruption_spread: match args.corruption_spread {
+            Spread::Fixed => CorruptionSpread::Fixed,
+            Spread::Binomial => CorruptionSpread::Binomial,
+            Spread::Poisson => CorruptionSpread::Poisson,
+        },
+        bootstrap_resamples: args.bootstrap.unwrap_or(0),
+        track_mass: args.mass_drift,
+    };
+    if let Err(error) = config.validate() {
+        validation_error(error);
+    }
+    config
+}
 
-                if !is_success {
-                    is_success = true;
+/// An option of a `compare` command line: the argument it sets (`None` if it is not one), its value if attached to it
+/// with `=`, and all tokens it spans, its values included
+type CompareOption<'a> = (Option<&'a Arg>, Option<String>, Vec<OsString>);
+
+/// Split the `tokens` of a `compare` command line into its options, given as `--name value`, `--name=value`, `-n value`,
+/// `-nvalue` or `-n=value`. Clusters of short flags such as `-ab` are split into one option per flag.
+fn split_options<'a>(compare: &'a clap::Command, tokens: &[OsString]) -> Vec<CompareOption<'a>> {
+    let mut options: Vec<CompareOption> = Vec::new();
+    let mut tokens = tokens.iter().peekable();
+    while let Some(token) = tokens.next() {
+        // Options are valid UTF-8, only values (e.g. paths) may not be
+        let text = token.to_str().unwrap_or_default();
+        let mut option = if let Some(option) = text.strip_prefix("--").filter(|option| !option.is_empty()) {
+            let (name, value) = option.split_once('=').map_or((option, None), |(name, value)| (name, Some(value.to_string())));
+            (compare.get_arguments().find(|arg| arg.get_long() == Some(name)), value, vec![token.clone()])
+        } else if let Some(flags) = text.strip_prefix('-').filter(|flags| flags.starts_with(|c: char| c.is_ascii_alphabetic())) {
+            let mut option = (None, None, vec![token.clone()]);
+            for (index, flag) in flags.char_indices() {
+                let Some(arg) = compare.get_arguments().find(|arg| arg.get_short() == Some(flag)) else {
+                    option = (None, None, vec![format!("-{}", &flags[index..]).into()]);
+                    break;
+                };
+                // The rest of the cluster is the value of the option, if there is a rest
+                let rest = &flags[index + flag.len_utf8()..];
+                if arg.get_action().takes_values() || rest.is_empty() {
+                    let value = Some(rest.strip_prefix('=').unwrap_or(rest).to_string()).filter(|value| !value.is_empty());
+                    let tokens = vec![value.as_ref().map_or(format!("-{flag}"), |value| format!("-{flag}={value}")).into()];
+                    option = (Some(arg), value, tokens);
+                    break;
                 }
+                options.push((Some(arg), None, vec![format!("-{flag}").into()]));
+            }
+            option
+        } else {
+            (None, None, vec![token.clone()])
+        };
+
+        // A value that is not attached is the next token, and further ones follow if the option takes several
+        if let (Some(arg), None) = (option.0, &option.1) {
+            if arg.get_action().takes_values() {
+                let most_values = arg.get_num_args().map_or(1, |values| values.max_values());
+                option.2.extend(tokens.next().cloned());
+                while option.2.len() <= most_values && tokens.peek().is_some_and(|token| !token.to_string_lossy().starts_with('-')) {
+                    option.2.extend(tokens.next().cloned());
+                }
+            }
+        }
+        options.push(option);
+    }
+    options
+}
+
+/// Return the tokens that give `arg` the values it got on the command line of `matches`, e.g. `--name=value`
+fn option_tokens(arg: &Arg, matches: &ArgMatches) -> Vec<OsString> {
+    let name = arg.get_long().map_or_else(|| format!("-{}", arg.get_short().expect("options are named")), |long| format!("--{long}"));
+    if !arg.get_action().takes_values() {
+        return vec![name.into()];
+    }
+    let values: Vec<&OsStr> = matches.get_raw(arg.get_id().as_str()).into_iter().flatten().collect();
+    // Attach the values, so that values starting with `-` are not taken for options
+    let attached = |values: &[&OsStr]| {
+        let mut token = OsString::from(format!("{name}="));
+        for (index, value) in values.iter().enumerate() {
+            if index > 0 {
+                token.push(arg.get_value_delimiter().expect("options with several values are delimited").to_string());
+            }
+            token.push(value);
+        }
+        token
+    };
+    match arg.get_value_delimiter() {
+        Some(_) => vec![attached(&values)],
+        None => values.iter().map(|value| attached(&[value])).collect(),
+    }
+}
+
+/// Return the command line of arm B of `compare` after the subcommand: the options that `arm_a` got on the command
+/// line, with every option that is given in `overrides` replaced by theirs. An override `--<flag>=false` removes a
+/// flag of arm A instead.
+fn override_args(arm_a: &ArgMatches, overrides: &[OsString]) -> Vec<OsString> {
+    let mut command = Cli::command();
+    command.build();
+    let compare = command.find_subcommand("compare").expect("compare is a subcommand");
+    let overrides = split_options(compare, overrides);
+    let overridden: HashSet<&clap::Id> = overrides.iter().filter_map(|(arg, _, _)| arg.map(Arg::get_id)).collect();
+    let negated = |(arg, value, _): &CompareOption| arg.is_some_and(|arg| !arg.get_action().takes_values()) && value.as_deref() == Some("false");
+
+    // The options of arm A, in the order they were given
+    let mut given: Vec<&Arg> = compare.get_arguments()
+        .filter(|arg| !arg.is_positional() && !overridden.contains(arg.get_id()))
+        .filter(|arg| arm_a.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .collect();
+    given.sort_by_key(|arg| arm_a.index_of(arg.get_id().as_str()));
+
+    let mut merged: Vec<OsString> = given.into_iter().flat_map(|arg| option_tokens(arg, arm_a)).collect();
+    merged.extend(overrides.iter().filter(|option| !negated(option)).flat_map(|(_, _, tokens)| tokens.iter().cloned()));
+    merged
+}
+
+/// Sweep the corruption `thresholds` with the configuration (and generator) of both `arms`, which share
+/// the seed of every threshold, and write how much later arm B hides the targets than arm A, and
+/// whether that is significant at level `alpha`
+fn compare(out: &mut dyn Write, arms: [(&Args, &SimulationConfig); 2], seed: u64, thresholds: &[f64], alpha: f64) -> io::Result<()> {
+    writeln!(out, "fraction_corrupted,success_round_a,success_round_b,success_round_delta,mean_round_a,mean_round_b,mean_round_delta,p_value,significant")?;
+    for &fraction_corrupted_commitments in thresholds {
+        let seed = threshold_seed(seed, fraction_corrupted_commitments);
+        let run = |args: &Args, config: &SimulationConfig| {
+            args.rng.run_sim(&SimulationConfig { seed, ..config.clone() }, fraction_corrupted_commitments)
+                .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error))
+        };
+        let [a, b] = arms.map(|(args, config)| run(args, config));
+
+        let mean = |result: &SimulationResult| result.hiding_round_stats.map(|stats| stats.mean);
+        let success_delta = a.success_round.zip(b.success_round).map(|(a, b)| b as i64 - a as i64);
+        let mean_delta = mean(&a).zip(mean(&b)).map(|(a, b)| b - a);
+        let p_value = rank_sum_p_value(&a.hiding_rounds, &b.hiding_rounds);
+        writeln!(out, "{fraction_corrupted_commitments},{},{},{},{},{},{},{p_value},{}", optional(a.success_round), optional(b.success_round),
+                 optional(success_delta), optional(mean(&a)), optional(mean(&b)), optional(mean_delta), p_value < alpha)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Sweep the corruption thresholds (and shuffle sizes and vector lengths) of `sweep` in parallel, and write the results
+/// of every threshold in sweep order, or what the sweep was asked to write instead
+fn run_sweep(out: &mut dyn Write, sweep: &SweepArgs, seed: u64, base_config: &SimulationConfig) -> io::Result<()> {
+    let args = &sweep.options;
+    let vector_lengths = sweep.vector_length_sweep.clone().unwrap_or_else(|| vec![args.vector_length]);
+    if let Some(shuffle_size_sweep) = sweep.shuffle_size_sweep {
+        let shortest = vector_lengths.iter().copied().min().unwrap_or(args.vector_length);
+        if shuffle_size_sweep.max > shortest {
+            validation_error(format!("shuffle size ({}) cannot exceed vector length ({shortest})", shuffle_size_sweep.max));
+        }
+        if args.shuffle_size_dist != SizeDist::Constant {
+            validation_error("only a constant shuffle size can be swept or searched");
+        }
+    }
+    if sweep.histogram == Some(0) {
+        validation_error("histogram bucket size must be positive");
+    }
+    if let Some(eps) = sweep.eps_list.iter().flatten().find(|&&eps| !(eps > 0.0 && eps < 1.0)) {
+        validation_error(format!("hiding threshold ({eps}) of --eps-list must be in (0, 1)"));
+    }
+    if sweep.eps_list.is_some() && args.format == OutputFormat::Matrix {
+        validation_error("the matrix format has no room for several hiding thresholds; use --format csv with --eps-list");
+    }
+    if sweep.vector_length_sweep.is_some() && args.format == OutputFormat::Matrix {
+        validation_error("the matrix format has no room for several vector lengths; use --format csv with --vector-length-sweep");
+    }
+
+    let thresholds = sweep.thresholds.fractions(base_config);
+    let shuffle_sizes: Vec<usize> = match sweep.shuffle_size_sweep {
+        Some(ShuffleSizeSweep { min, max, step }) => (min..=max).step_by(step).collect(),
+        None => vec![args.shuffle_size],
+    };
+    // Every vector length and shuffle size is validated up front, so that a sweep does not fail halfway
+    let mut configs = Vec::new();
+    // Streams and lists of hiding thresholds run one threshold after the other, anything else runs them in parallel
+    let running = if sweep.stream || sweep.eps_list.is_some() { 1 } else { thresholds.len() * shuffle_sizes.len() };
+    for &vector_length in &vector_lengths {
+        warn_if_large(vector_length, base_config, running);
+        for &shuffle_size in &shuffle_sizes {
+            let config = SimulationConfig { vector_length, shuffle_size, ..base_config.clone() };
+            if let Err(error) = config.validate() {
+                validation_error(error);
             }
+            configs.push(config);
+        }
+    }
+    // Each threshold gets its own base seed for its repetitions
+    let grid: Vec<(SimulationConfig, f64)> = configs.iter()
+        .flat_map(|config| thresholds.iter().map(move |&fraction| {
+            (SimulationConfig { seed: threshold_seed(seed, fraction), ..config.clone() }, fraction)
+        }))
+        .collect();
+
+    if sweep.dry_run {
+        // The whole configuration is written at once below
+    } else if sweep.stream {
+        // Every line is a JSON object of its own
+    } else if sweep.dump_curve {
+        writeln!(out, "fraction_corrupted,round,probability")?;
+    } else if sweep.dump_spread {
+        writeln!(out, "fraction_corrupted,round,cups_above_eps")?;
+    } else if sweep.dump_differences {
+        writeln!(out, "fraction_corrupted,round,secret_difference")?;
+    } else if sweep.percentiles {
+        let columns: Vec<String> = PERCENTILES.iter().map(|p| format!("p{p}")).collect();
+        writeln!(out, "fraction_corrupted,{}", columns.join(","))?;
+    } else if sweep.histogram.is_some() {
+        writeln!(out, "fraction_corrupted,bucket_start,count")?;
+    } else {
+        write_header(out, args, seed, &thresholds)?;
+    }
+
+    // The bar is drawn on stderr, but is kept away from anything that is piped or parsed
+    let progress = if sweep.progress && io::stdout().is_terminal() {
+        ProgressBar::new(grid.len() as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} thresholds [{elapsed_precise}, ETA {eta}]").unwrap());
+
+    if sweep.dry_run {
+        let resolved: Vec<ResolvedThreshold> = grid.iter().map(|(config, fraction_corrupted_commitments)| {
+            let (corrupted_commitments, target_eps) = threshold_parameters(config, *fraction_corrupted_commitments)
+                .unwrap_or_else(|error| validation_error(error));
+            ResolvedThreshold { fraction_corrupted: *fraction_corrupted_commitments, corrupted_commitments, target_eps, config: config.clone() }
+        }).collect();
+        serde_json::to_writer_pretty(&mut *out, &resolved)?;
+        writeln!(out)?;
+        return out.flush();
+    }
+
+    // Thresholds are streamed one after the other, so that their rounds are not interleaved
+    if sweep.stream {
+        for (config, fraction_corrupted_commitments) in &grid {
+            args.rng.write_stream(out, config, *fraction_corrupted_commitments)?;
+        }
+        return Ok(());
+    }
+
+    // Every threshold already runs its repetitions in parallel, and writes a row for every target level in order
+    if let Some(eps_list) = &sweep.eps_list {
+        let mut index = 0;
+        for &(ref config, fraction_corrupted_commitments) in &grid {
+            let start = Instant::now();
+            let results = args.rng.run_sim_for_eps(config, fraction_corrupted_commitments, eps_list)
+                .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+            // The levels share one simulation, so they share its time as well
+            let elapsed = start.elapsed();
+            for result in &results {
+                write_result(out, args, seed, index, &thresholds, result, Some(elapsed))?;
+                index += 1;
+            }
+            out.flush()?;
+            progress.inc(1);
+        }
+        progress.finish_and_clear();
+        write_footer(out, args)?;
+        return out.flush();
+    }
+
+    // Thousands of lines per threshold, so they are buffered and only flushed with every threshold
+    let mut raw = match &sweep.dump_raw {
+        Some(path) => {
+            let mut raw = BufWriter::new(File::create(path)?);
+            writeln!(raw, "fraction_corrupted,repetition,round")?;
+            Some(raw)
+        }
+        None => None,
+    };
+
+    let (checkpointed, mut checkpoint) = match &sweep.checkpoint {
+        Some(path) => {
+            let (results, file) = load_checkpoint(path)?;
+            (results, Some(BufWriter::new(file)))
+        }
+        None => (HashMap::new(), None),
+    };
 
-            // Sanity check: no water was placed in bad, i.e. corupted or opened, cups
-            for (index, water) in water_cups.iter() {
-                if bad_commitment_indices.contains(index) {
-                    assert_eq!(*water, 0.0);
+    // The first Ctrl-C lets the running thresholds finish and writes what is done, a second one exits right away. The
+    // handler only sets the flag, so the results are still only written by the receiver below.
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("interrupted: finishing the running thresholds, press Ctrl-C again to exit right away");
+    }).map_err(io::Error::other)?;
+
+    // Thresholds are simulated in parallel, and finish in any order. Results are written in sweep
+    // order as soon as all thresholds before them are done.
+    let (sender, receiver) = mpsc::channel();
+    // Only collected for the summary, as results are otherwise written as soon as possible
+    let mut summarized = Vec::new();
+    let mut finished = BTreeMap::new();
+    let mut next_index = 0;
+    thread::scope(|scope| -> io::Result<()> {
+        scope.spawn(|| {
+            grid.par_iter().enumerate().for_each_with(sender, |sender, (index, &(ref config, fraction_corrupted_commitments))| {
+                let key = (config.vector_length, config.shuffle_size, fraction_corrupted_commitments.to_bits());
+                let message = match checkpointed.get(&key) {
+                    Some(result) => (index, Ok(result.clone()), None),
+                    None if INTERRUPTED.load(Ordering::SeqCst) => return,
+                    None => {
+                        // Only the simulation is timed, writing the result is left to the receiver
+                        let start = Instant::now();
+                        let result = args.rng.run_sim(config, fraction_corrupted_commitments);
+                        (index, result, Some(start.elapsed()))
+                    }
+                };
+                // The receiver only hangs up if writing failed, in which case that error is reported
+                let _ = sender.send(message);
+            });
+        });
+
+        for (index, result, elapsed) in receiver {
+            // The configuration was validated, so this is a simulation going wrong; its results cannot be trusted
+            let result = result.unwrap_or_else(|error| simulation_error(grid[index].1, error));
+            // Checkpoint in completion order, so that nothing finished is lost if the sweep is interrupted
+            if let (Some(checkpoint), Some(_)) = (&mut checkpoint, elapsed) {
+                serde_json::to_writer(&mut *checkpoint, &result)?;
+                writeln!(checkpoint)?;
+                checkpoint.flush()?;
+            }
+            finished.insert(index, (result, elapsed));
+            while let Some((result, elapsed)) = finished.remove(&next_index) {
+                progress.suspend(|| {
+                    if result.success_round.is_none() && result.is_still_improving() {
+                        warn!("fraction {}: the targets were never hidden in every repetition, but the success probability \
+                               was still increasing in the last rounds; consider raising --max-shuffles (currently {})",
+                              result.fraction_corrupted, args.max_shuffles);
+                    }
+                    if sweep.dump_curve {
+                        write_curve(out, args.repetitions, &result)?;
+                    } else if sweep.dump_spread {
+                        write_spread(out, args.repetitions, &result)?;
+                    } else if sweep.dump_differences {
+                        write_differences(out, &result)?;
+                    } else if sweep.percentiles {
+                        write_percentiles(out, &result)?;
+                    } else if let Some(bucket_size) = sweep.histogram {
+                        write_histogram(out, bucket_size, &result)?;
+                    } else {
+                        write_result(out, args, seed, next_index, &thresholds, &result, elapsed)?;
+                    }
+                    if let Some(raw) = &mut raw {
+                        write_raw(raw, &result)?;
+                        raw.flush()?;
+                    }
+                    // Flush after every threshold, so that an interrupted sweep keeps what it finished
+                    out.flush()
+                })?;
+                if sweep.summary {
+                    summarized.push(result);
                 }
+                next_index += 1;
             }
+            progress.inc(1);
+        }
+        Ok(())
+    })?;
+    progress.finish_and_clear();
+
+    // The output stays in sweep order, so it ends with the last threshold before the first one that was skipped
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+    if interrupted {
+        warn!("interrupted: wrote {next_index} of {} thresholds", grid.len());
+        if !finished.is_empty() {
+            match sweep.checkpoint {
+                Some(_) => warn!("{} thresholds finished out of order and are only in the checkpoint", finished.len()),
+                None => warn!("{} thresholds finished out of order and were dropped; pass --checkpoint to keep them", finished.len()),
+            }
+        }
+        if args.format == OutputFormat::Matrix && next_index % thresholds.len() != 0 {
+            writeln!(out)?;
+        }
+    }
+
+    if !sweep.dump_curve && !sweep.dump_spread && !sweep.dump_differences && !sweep.percentiles && sweep.histogram.is_none() {
+        write_footer(out, args)?;
+    }
+
+    // The summary is meant for reading, so it stays out of the data on stdout
+    if sweep.summary {
+        summarized.sort_by(|a, b| a.vector_length.cmp(&b.vector_length).then(a.shuffle_size.cmp(&b.shuffle_size))
+            .then(a.fraction_corrupted.total_cmp(&b.fraction_corrupted)));
+        write_summary(&mut io::stderr(), &summarized, sweep.vector_length_sweep.is_some(), sweep.shuffle_size_sweep.is_some())?;
+    }
+
+    out.flush()?;
+    if interrupted {
+        process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// Search the critical corruption fraction of `critical`, and write the fractions on both sides of it
+fn run_critical(out: &mut dyn Write, critical: &CriticalArgs, seed: u64, base_config: &SimulationConfig) -> io::Result<()> {
+    if !(critical.precision > 0.0 && critical.precision < 100.0) {
+        validation_error("precision must be in (0, 100) percent");
+    }
+    if fixed_threshold(base_config).is_some() {
+        validation_error("the search picks its own corruption thresholds, so it cannot start from corrupted cups");
+    }
+    let config = SimulationConfig { seed, ..base_config.clone() };
+    let found = critical.options.rng.find_critical_threshold(&config, critical.precision / 100.0)
+        .unwrap_or_else(|error| simulation_error(0.0, error));
+    writeln!(out, "fraction_hidden,fraction_never_hidden,success_round")?;
+    match found {
+        Some(CriticalThreshold { hidden, never_hidden }) => {
+            if never_hidden == 1.0 {
+                warn!("every corruption fraction that was simulated hid the targets within {} rounds", critical.options.max_shuffles);
+            }
+            writeln!(out, "{},{never_hidden},{}", hidden.fraction_corrupted, optional(hidden.success_round))?;
+        }
+        None => {
+            warn!("the targets are not hidden within {} rounds, even without corruption", critical.options.max_shuffles);
+            writeln!(out, ",,")?;
         }
     }
+    out.flush()
+}
+
+/// Vector lengths from which a sweep warns about the memory it takes
+const LARGE_VECTOR_LENGTH: usize = 1 << 22;
 
-    let successful_round = get_success_round(sum_succ_in_round);
-    println!("Simulation parameters: [{VECTOR_LENGTH} {SHUFFLE_SIZE}] [{fraction_corrupted_commitments} {target_eps}]: {successful_round}");
+/// Warn if the cups of a vector of `vector_length` take a lot of memory. Every threshold sets up all of its repetitions
+/// at once, each holding the water of every target in every cup, and up to `thresholds` of them run at the same time.
+fn warn_if_large(vector_length: usize, config: &SimulationConfig, thresholds: usize) {
+    if vector_length < LARGE_VECTOR_LENGTH {
+        return;
+    }
+    let targets = config.target_cups.len();
+    let bytes = vector_length * (targets * std::mem::size_of::<f64>() + std::mem::size_of::<bool>());
+    let per_threshold = bytes * config.repetitions;
+    let running = thresholds.min(rayon::current_num_threads());
+    warn!("vector length {vector_length}: every threshold holds about {} MiB of cups for its {} repetitions, and {running} \
+           run at the same time (about {} MiB)", per_threshold >> 20, config.repetitions, (per_threshold * running) >> 20);
 }
 
-fn main() {
-    // Run simulations for corruption thresholds from 1% to 49%
-    for p in 1..=49 {
-        // Fraction of corrupted cards
-        let fraction_corrupted_commitments: f64 = p as f64/100.0;
-        let corrupted_commitments: usize = ((VECTOR_LENGTH as f64) * fraction_corrupted_commitments) as usize;
+/// Simulate the single threshold of `single` and write its results
+fn run_single(out: &mut dyn Write, single: &SingleArgs, seed: u64, base_config: &SimulationConfig) -> io::Result<()> {
+    let args = &single.options;
+    let fraction_corrupted_commitments = match (single.corruption, fixed_threshold(base_config)) {
+        (_, Some(fraction)) => fraction,
+        (Some(percent), None) if (0.0..100.0).contains(&percent) => corruption_thresholds(percent, percent, 1.0)[0],
+        (Some(_), None) => validation_error("corruption threshold must be at least 0% and below 100%"),
+        (None, None) => unreachable!("clap requires a threshold"),
+    };
+    // The seed is derived as in a sweep, so that the result is the same as for this threshold of any sweep
+    let config = SimulationConfig { seed: threshold_seed(seed, fraction_corrupted_commitments), ..base_config.clone() };
+    let thresholds = [fraction_corrupted_commitments];
+    write_header(out, args, seed, &thresholds)?;
+    let start = Instant::now();
+    let result = args.rng.run_sim(&config, fraction_corrupted_commitments)
+        .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+    let elapsed = start.elapsed();
+    if result.success_round.is_none() && result.is_still_improving() {
+        warn!("fraction {fraction_corrupted_commitments}: the targets were never hidden in every repetition, but the success \
+               probability was still increasing in the last rounds; consider raising --max-shuffles (currently {})", args.max_shuffles);
+    }
+    write_result(out, args, seed, 0, &thresholds, &result, Some(elapsed))?;
+    write_footer(out, args)?;
+    out.flush()
+}
 
-        // Target Water level
-        let target_eps: f64 = 4.0 / (VECTOR_LENGTH as f64 * (1.0 - fraction_corrupted_commitments));
+/// Trace a single repetition of every threshold of `trace`, or continue the saved one
+fn run_trace(out: &mut dyn Write, trace: &TraceArgs, seed: u64, base_config: &SimulationConfig) -> io::Result<()> {
+    let resume: Option<StateSnapshot> = trace.resume_state.as_deref().map(|path| {
+        let snapshot = fs::read_to_string(path).map_err(|error| error.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|error| error.to_string()));
+        snapshot.unwrap_or_else(|error| validation_error(format!("cannot read saved state {}: {error}", path.display())))
+    });
+    let thresholds = match &resume {
+        Some(snapshot) => vec![snapshot.fraction_corrupted],
+        None => trace.thresholds.fractions(base_config),
+    };
+    if trace.save_state.is_some() && thresholds.len() != 1 {
+        validation_error("--save-state saves a single repetition; pick one threshold with --corruption-min and --corruption-max");
+    }
 
-        run_sim(fraction_corrupted_commitments, corrupted_commitments, target_eps);
+    // A single repetition per threshold is quick, so there is no need for the parallel sweep
+    writeln!(out, "fraction_corrupted,round,max_water")?;
+    for fraction_corrupted_commitments in thresholds {
+        let config = SimulationConfig { seed: threshold_seed(seed, fraction_corrupted_commitments), ..base_config.clone() };
+        let (max_water, snapshot) = trace.options.rng.trace_repetition(&config, fraction_corrupted_commitments, resume.as_ref())
+            .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+        // A continued repetition goes on counting from the round it was saved in
+        let first_round = resume.as_ref().map_or(0, |snapshot| snapshot.round);
+        for (t, max_water) in max_water.iter().enumerate() {
+            writeln!(out, "{fraction_corrupted_commitments},{},{max_water}", first_round + t + 1)?;
+        }
+        if let Some(path) = &trace.save_state {
+            let mut file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer(&mut file, &snapshot)?;
+            file.flush()?;
+        }
+    }
+    out.flush()
+}
+
+/// Search the smallest shuffle size for every threshold of `find`
+fn run_find_min(out: &mut dyn Write, find: &FindMinArgs, seed: u64, base_config: &SimulationConfig) -> io::Result<()> {
+    if find.options.shuffle_size_dist != SizeDist::Constant {
+        validation_error("only a constant shuffle size can be swept or searched");
+    }
+    if find.deadline == 0 {
+        validation_error("deadline must be at least 1 round");
+    }
+    let deadline = find.deadline;
+    writeln!(out, "fraction_corrupted,min_shuffle_size,success_round")?;
+    // Every candidate of the search already runs its repetitions in parallel
+    for fraction_corrupted_commitments in find.thresholds.fractions(base_config) {
+        let config = SimulationConfig { seed: threshold_seed(seed, fraction_corrupted_commitments), ..base_config.clone() };
+        let found = find.options.rng.find_min_shuffle_size(&config, fraction_corrupted_commitments, deadline)
+            .unwrap_or_else(|error| simulation_error(fraction_corrupted_commitments, error));
+        match found {
+            Some(result) => writeln!(out, "{fraction_corrupted_commitments},{},{}", result.shuffle_size, optional(result.success_round))?,
+            None => {
+                warn!("fraction {fraction_corrupted_commitments}: the targets are not hidden within {deadline} rounds, \
+                       even with a shuffle size of {}", find.options.vector_length);
+                writeln!(out, "{fraction_corrupted_commitments},,")?;
+            }
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let matches = cli_matches(std::env::args_os());
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    // The search picks its own shuffle sizes, so the default must not fail validation on small vectors
+    if let Command::FindMinShuffleSize(find) = &mut cli.command {
+        find.options.shuffle_size = find.options.vector_length;
+    }
+    let args = cli.command.options();
+    // Data goes to stdout and diagnostics to stderr; warnings are shown by default, `RUST_LOG=debug`
+    // or `trace` shows more, and without a logger nothing is shown at all
+    if !args.quiet {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    }
+    // Draw a seed if none was given, so that every run can be reproduced
+    let seed = args.seed.unwrap_or_else(|| thread_rng().gen());
+    let base_config = simulation_config(args, seed);
+
+    // Stdout is not locked for the whole run, as the simulation itself may print warnings from worker threads
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    match &cli.command {
+        Command::Sweep(sweep) => run_sweep(&mut out, sweep, seed, &base_config),
+        Command::Single(single) => run_single(&mut out, single, seed, &base_config),
+        Command::Trace(trace) => run_trace(&mut out, trace, seed, &base_config),
+        Command::FindMinShuffleSize(find) => run_find_min(&mut out, find, seed, &base_config),
+        Command::CriticalThreshold(critical) => run_critical(&mut out, critical, seed, &base_config),
+        // Both arms sweep the thresholds of arm A, with its seed, so that they differ in nothing but the overrides
+        Command::Compare(CompareArgs { options, thresholds, alpha, overrides }) => {
+            if !(*alpha > 0.0 && *alpha < 1.0) {
+                validation_error("significance level must be in (0, 1)");
+            }
+            // Arm A is what was given before the `--`, as clap parsed it
+            let arm_a = matches.subcommand_matches("compare").expect("the subcommand was parsed");
+            let mut argv_b: Vec<OsString> = std::env::args_os().take(1).collect();
+            argv_b.push("compare".into());
+            argv_b.extend(override_args(arm_a, overrides));
+            argv_b.push("--".into());
+            argv_b.extend(overrides.iter().cloned());
+            let options_b = match parse_cli(argv_b).command {
+                Command::Compare(compare_b) => compare_b.options,
+                _ => unreachable!("arm B is parsed as compare as well"),
+            };
+            let config_b = simulation_config(&options_b, seed);
+            compare(&mut out, [(options, &base_config), (&options_b, &config_b)], seed, &thresholds.fractions(&base_config), *alpha)?;
+            out.flush()
+        }
     }
 }
@@ -0,0 +1,38 @@
+use std::ops::ControlFlow;
+use std::sync::Mutex;
+
+use aqua_shuffle_sim::{run_sim, run_sim_streaming, run_sim_with_callback, SimulationConfig};
+
+/// Run `config` with a callback, and check that it gets every shuffle of every round of every repetition once, in order
+fn assert_called_after_every_shuffle(config: &SimulationConfig) {
+    let calls = Mutex::new(vec![Vec::new(); config.repetitions]);
+    let callback = |round, shuffle, repetition: usize, _| {
+        calls.lock().unwrap()[repetition].push((round, shuffle));
+        ControlFlow::Continue(())
+    };
+    run_sim_with_callback(config, 0.1, Some(&callback)).unwrap();
+
+    // The stream yields every round that was run, until the early exit if there is one
+    let rounds = run_sim_streaming(config, 0.1).unwrap().count();
+    assert!(config.early_exit || rounds == config.max_shuffles, "only {rounds} rounds were run without an early exit");
+    let shuffles: Vec<(usize, usize)> = (1..=rounds).flat_map(|round| (1..=config.shuffles_per_round).map(move |shuffle| (round, shuffle))).collect();
+    for (repetition, calls) in calls.into_inner().unwrap().iter().enumerate() {
+        assert_eq!(calls.len(), rounds * config.shuffles_per_round, "repetition {repetition} was called back a wrong number of times");
+        assert_eq!(calls, &shuffles, "repetition {repetition} was called back out of order");
+    }
+}
+
+#[test]
+fn callback_runs_after_every_shuffle() {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 8, max_shuffles: 500, repetitions: 6, seed: 2, ..SimulationConfig::default() };
+    assert_called_after_every_shuffle(&config);
+    assert_called_after_every_shuffle(&SimulationConfig { shuffles_per_round: 3, ..config.clone() });
+    assert_called_after_every_shuffle(&SimulationConfig { max_shuffles: 40, early_exit: false, ..config });
+}
+
+#[test]
+fn no_callback_is_run_sim() {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 8, max_shuffles: 500, repetitions: 6, seed: 2, repetition_chunk: Some(4),
+                                    ..SimulationConfig::default() };
+    assert_eq!(run_sim_with_callback(&config, 0.1, None).unwrap(), run_sim(&config, 0.1).unwrap());
+}
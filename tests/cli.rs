@@ -0,0 +1,146 @@
+use std::process::{Command, Output};
+
+/// Run the simulator with `args` and the environment variables `env`, checking only the resolved configuration
+fn dry_run(args: &[&str], env: &[(&str, &str)]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"));
+    command.args(["sweep", "--dry-run", "--vector-length", "64", "--shuffle-size", "4"]).args(args).envs(env.iter().copied());
+    command.output().expect("the simulator runs")
+}
+
+/// Return the `field` of every threshold that a dry run resolved
+fn resolved(output: &Output, field: &str) -> Vec<String> {
+    assert!(output.status.success(), "dry run failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| line.trim().strip_prefix(&format!("\"{field}\": ")).map(str::to_string))
+        .map(|value| value.trim_end_matches(',').to_string())
+        .collect()
+}
+
+/// Return the `fraction_corrupted` of every threshold that a dry run resolved
+fn fractions(output: &Output) -> Vec<String> {
+    resolved(output, "fraction_corrupted")
+}
+
+#[test]
+fn command_line_overrides_conflicting_environment_variable() {
+    let output = dry_run(&["--corruption-list", "0.1,0.2"], &[("AQUASHUFFLE_CORRUPTION_MAX", "5")]);
+    assert_eq!(fractions(&output), ["0.1", "0.2"]);
+}
+
+#[test]
+fn environment_variable_applies_without_conflict() {
+    let output = dry_run(&[], &[("AQUASHUFFLE_CORRUPTION_MAX", "3")]);
+    assert_eq!(fractions(&output), ["0.01", "0.02", "0.03"]);
+}
+
+#[test]
+fn corruption_list_takes_fractions() {
+    assert_eq!(fractions(&dry_run(&["--corruption-list", "0.1,0.33"], &[])), ["0.1", "0.33"]);
+    assert!(!dry_run(&["--corruption-list", "10,33"], &[]).status.success(), "percent were taken for fractions");
+}
+
+#[test]
+fn corrupted_count_list_corrupts_exactly_these_counts() {
+    let output = dry_run(&["--corrupted-count-list", "5,1,21"], &[]);
+    assert_eq!(resolved(&output, "corrupted_commitments"), ["5", "1", "21"]);
+    assert_eq!(fractions(&output), ["0.078125", "0.015625", "0.328125"]);
+
+    // Of 100 cups, the fractions of 29, 57 and 58 cups are rounded down
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"));
+    let counts: Vec<String> = (0..100).map(|count| count.to_string()).collect();
+    command.args(["sweep", "--dry-run", "--vector-length", "100", "--shuffle-size", "4", "--corrupted-count-list", &counts.join(",")]);
+    assert_eq!(resolved(&command.output().expect("the simulator runs"), "corrupted_commitments"), counts);
+
+    assert!(!dry_run(&["--corrupted-count-list", "64"], &[]).status.success(), "a count of all cups was accepted");
+    assert!(!dry_run(&["--corrupted-count-list", "3,4,3"], &[]).status.success(), "a duplicate count was accepted");
+}
+
+#[test]
+fn conflicting_environment_variables_are_rejected() {
+    let output = dry_run(&[], &[("AQUASHUFFLE_CORRUPTION_MAX", "5"), ("AQUASHUFFLE_CORRUPTION_LIST", "0.1")]);
+    assert!(!output.status.success(), "both variables were accepted");
+}
+
+/// Compare arm A with the options `arm_a` against arm B with `overrides`, and return the columns of both arms
+fn compare_arms(arm_a: &[&str], overrides: &[&str]) -> (Vec<String>, Vec<String>) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"));
+    command.args(["compare", "--vector-length", "64", "--repetitions", "5", "--corruption-max", "2", "--seed", "1"])
+        .args(arm_a).arg("--").args(overrides).env_clear();
+    let output = command.output().expect("the simulator runs");
+    assert!(output.status.success(), "compare failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let rows: Vec<Vec<String>> = String::from_utf8_lossy(&output.stdout).lines().skip(1)
+        .map(|line| line.split(',').map(str::to_string).collect())
+        .collect();
+    let arm = |columns: [usize; 2]| rows.iter().flat_map(|row| columns.map(|column| row[column].clone())).collect();
+    (arm([1, 4]), arm([2, 5]))
+}
+
+#[test]
+fn compare_overrides_options_in_any_form() {
+    let (arm_a, _) = compare_arms(&["--shuffle-size", "8"], &["--shuffle-size", "4"]);
+    for (arm_a_options, overrides) in [(&["--shuffle-size=4"][..], &["--shuffle-size", "8"][..]), (&["--shuffle-size", "4"], &["--shuffle-size=8"]),
+                                       (&["-q", "--shuffle-size", "4"], &["--quiet", "--shuffle-size", "8"])] {
+        let (_, arm_b) = compare_arms(arm_a_options, overrides);
+        assert_eq!(arm_b, arm_a, "arm B of {arm_a_options:?} -- {overrides:?} is not the one of the overrides");
+    }
+}
+
+#[test]
+fn compare_turns_off_flags_of_arm_a() {
+    let (arm_a, _) = compare_arms(&["--shuffle-size", "4", "--fixed-schedule"], &["--shuffle-size", "4"]);
+    let (_, arm_b) = compare_arms(&["--shuffle-size", "4"], &["--fixed-schedule"]);
+    assert_eq!(arm_b, arm_a);
+
+    let (arm_a, arm_b) = compare_arms(&["--shuffle-size", "4", "--fixed-schedule"], &["--fixed-schedule=false"]);
+    let (no_flag, _) = compare_arms(&["--shuffle-size", "4"], &["--shuffle-size", "4"]);
+    assert_ne!(arm_a, no_flag, "--fixed-schedule changes nothing, so the test cannot tell whether it was turned off");
+    assert_eq!(arm_b, no_flag);
+}
+
+#[cfg(unix)]
+#[test]
+fn compare_takes_paths_that_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::env::temp_dir().join(OsStr::from_bytes(b"compare-\xff.csv"));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"));
+    command.args(["compare", "--vector-length", "64", "--repetitions", "5", "--corruption-max", "2", "--seed", "1", "--shuffle-size", "8",
+                  "--output"]).arg(&path)
+        .args(["--", "--shuffle-size", "4"]).env_clear();
+    let output = command.output().expect("the simulator runs");
+    assert!(output.status.success(), "compare failed: {}", String::from_utf8_lossy(&output.stderr));
+    let written = std::fs::read_to_string(&path).expect("compare wrote its output");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written.lines().count(), 3);
+}
+
+/// Run a sweep of the thresholds of 1% and 2% on 64 cups in `format`, with the options `args`, and return its lines
+fn sweep_lines(format: &str, args: &[&str]) -> Vec<String> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"));
+    command.args(["sweep", "--vector-length", "64", "--shuffle-size", "8", "--repetitions", "5", "--seed", "1", "--corruption-max", "2",
+                  "--format", format, "-q"])
+        .args(args).env_clear();
+    let output = command.output().expect("the simulator runs");
+    assert!(output.status.success(), "sweep failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect()
+}
+
+#[test]
+fn legacy_format_writes_the_historical_lines() {
+    assert_eq!(sweep_lines("legacy", &[]), [
+        "Simulation parameters: [64 8] [0.01 0.06313131313131314]: 58",
+        "Simulation parameters: [64 8] [0.02 0.06377551020408163]: 39",
+    ]);
+}
+
+#[test]
+fn legacy_format_writes_0_for_targets_never_hidden() {
+    let text = sweep_lines("text", &["--max-shuffles", "1"]);
+    assert!(text.iter().all(|line| line.contains(": NEVER")), "the targets were hidden in the first round: {text:?}");
+    assert_eq!(sweep_lines("legacy", &["--max-shuffles", "1"]), [
+        "Simulation parameters: [64 8] [0.01 0.06313131313131314]: 0",
+        "Simulation parameters: [64 8] [0.02 0.06377551020408163]: 0",
+    ]);
+}
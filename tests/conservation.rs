@@ -0,0 +1,39 @@
+use aqua_shuffle_sim::{distribute_water, BatchScheduler, BatchSelector, MeanMix, MixRule, PairwiseSwapMix, SelectionWeights, ShuffleModel, Summation};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const EPSILON: f64 = 1e-12;
+
+/// Run many shuffles on a small vector and check the honest water never changes
+fn assert_water_conserved(rule: &dyn MixRule) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let vector_length = 64;
+
+    // Corrupt a quarter of the cups, but never cup 0 which holds the water
+    let corrupted: Vec<bool> = (0..vector_length).map(|index| index != 0 && rng.gen_bool(0.25)).collect();
+    let mut cups = vec![0.0; vector_length];
+    cups[0] = 1.0;
+
+    let mut batches = BatchSelector::new(BatchScheduler::RandomIid, SelectionWeights::Uniform);
+    for _ in 0..10_000 {
+        distribute_water(&mut cups, &corrupted, 0.0, 8, ShuffleModel::FullAverage, rule, f64::INFINITY, 0.0, Summation::Naive, &mut 0.0, &mut 0.0, &mut batches, &mut rng);
+
+        let honest_water: f64 = cups.iter().zip(&corrupted).filter(|(_, &is_bad)| !is_bad).map(|(water, _)| water).sum();
+        assert!((honest_water - 1.0).abs() < EPSILON, "honest water drifted to {honest_water}");
+    }
+}
+
+#[test]
+fn perfect_shuffle_conserves_water() {
+    assert_water_conserved(&MeanMix { mixing_factor: 1.0, summation: Summation::Naive });
+}
+
+#[test]
+fn leaky_shuffle_conserves_water() {
+    assert_water_conserved(&MeanMix { mixing_factor: 0.3, summation: Summation::Naive });
+}
+
+#[test]
+fn pairwise_swaps_conserve_water() {
+    assert_water_conserved(&PairwiseSwapMix);
+}
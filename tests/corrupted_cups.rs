@@ -0,0 +1,75 @@
+use aqua_shuffle_sim::{distribute_water, AdversaryStrategy, BatchScheduler, BatchSelector, MeanMix, SelectionWeights, ShuffleModel, SimulationConfig, SimulationState, Summation};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Run many shuffles on a small vector, now and then with orphaned water to pour, and check that
+/// no water ever ends up in a corrupted cup
+fn assert_corrupted_cups_stay_empty(mixing_factor: f64, model: ShuffleModel) {
+    for seed in 0..10 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let vector_length = 64;
+
+        // Corrupt a quarter of the cups, but never cup 0 which holds the water
+        let corrupted: Vec<bool> = (0..vector_length).map(|index| index != 0 && rng.gen_bool(0.25)).collect();
+        let mut cups = vec![0.0; vector_length];
+        cups[0] = 1.0;
+
+        let mut batches = BatchSelector::new(BatchScheduler::RandomIid, SelectionWeights::Uniform);
+        let rule = MeanMix { mixing_factor, summation: Summation::Naive };
+        for shuffle in 0..1_000 {
+            let mut orphaned = if shuffle % 10 == 0 { 0.1 } else { 0.0 };
+            distribute_water(&mut cups, &corrupted, 0.0, 8, model, &rule, f64::INFINITY, 0.0, Summation::Naive, &mut orphaned, &mut 0.0, &mut batches, &mut rng);
+
+            for (index, _) in corrupted.iter().enumerate().filter(|(_, &is_bad)| is_bad) {
+                assert_eq!(cups[index], 0.0, "corrupted cup {index} holds water after shuffle {shuffle} with seed {seed}");
+            }
+        }
+    }
+}
+
+#[test]
+fn perfect_shuffle_leaves_corrupted_cups_empty() {
+    assert_corrupted_cups_stay_empty(1.0, ShuffleModel::FullAverage);
+}
+
+#[test]
+fn partial_and_windowed_shuffles_leave_corrupted_cups_empty() {
+    assert_corrupted_cups_stay_empty(0.3, ShuffleModel::FullAverage);
+    assert_corrupted_cups_stay_empty(1.0, ShuffleModel::WindowedAverage { window: 3 });
+}
+
+/// Open cups progressively while the water spreads, and check that every opened cup is emptied,
+/// including cups that held water right before they were opened
+fn assert_opened_cups_are_emptied(redistribute: bool) {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 8, adversary: AdversaryStrategy::ProgressiveOpening { rate: 1, redistribute },
+                                    ..SimulationConfig::default() };
+    let mut opened_with_water = 0;
+    for repetition in 0..10 {
+        let mut state = SimulationState::new(&config, 0.5, repetition).unwrap();
+        for _ in 0..100 {
+            let before: Vec<f64> = (0..config.vector_length).map(|index| state.water().iter().map(|cups| cups[index]).sum()).collect();
+            let was_corrupted = state.corrupted().to_vec();
+            state.step();
+
+            for (index, _) in state.corrupted().iter().enumerate().filter(|(_, &is_bad)| is_bad) {
+                for cups in state.water() {
+                    assert_eq!(cups[index], 0.0, "opened cup {index} holds water in round {} of repetition {repetition}", state.round());
+                }
+                if !was_corrupted[index] && before[index] > 0.0 {
+                    opened_with_water += 1;
+                }
+            }
+        }
+    }
+    assert!(opened_with_water > 0, "no cup held water when it was opened, so the test checked nothing");
+}
+
+#[test]
+fn progressive_opening_empties_opened_cups() {
+    assert_opened_cups_are_emptied(false);
+}
+
+#[test]
+fn redistributed_opening_empties_opened_cups() {
+    assert_opened_cups_are_emptied(true);
+}
@@ -0,0 +1,14 @@
+use aqua_shuffle_sim::{run_sim, SimulationConfig};
+
+#[test]
+fn tolerance_hides_water_just_above_the_target_level() {
+    // Batches of a single cup move no water, so the target keeps all of it, just above the target level
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 1, max_shuffles: 1, repetitions: 3, target_eps: Some(1.0 - 1e-12),
+                                    ..SimulationConfig::default() };
+    assert_eq!(run_sim(&config, 0.0).unwrap().success_round, None);
+
+    let tolerant = SimulationConfig { eps_tolerance: 1e-9, ..config };
+    let result = run_sim(&tolerant, 0.0).unwrap();
+    assert_eq!(result.success_round, Some(1));
+    assert_eq!(result.target_eps, 1.0 - 1e-12, "the tolerance changed the reported target level");
+}
@@ -0,0 +1,48 @@
+use aqua_shuffle_sim::{max_water, MixRuleKind, ShuffleModel, SimulationConfig, SimulationState, Summation};
+
+/// Step the repetition for 200 rounds and check its fullest cup never gains water.
+///
+/// Debug builds also check this after every round inside the simulation, so this exercises that
+/// assertion too; the tolerance is the same.
+fn assert_fullest_cup_never_gains(state: &mut SimulationState) {
+    let mut before = max_water(&state.water()[0]);
+    for _ in 0..200 {
+        let after = state.step();
+        assert!(after - before <= 1e-9 * before.abs().max(1.0), "the fullest cup gained water in round {}, from {before} to {after}",
+                state.round());
+        before = after;
+    }
+}
+
+#[test]
+fn fullest_cup_never_gains_water() {
+    let base = SimulationConfig { vector_length: 64, shuffle_size: 8, repetitions: 1, seed: 5, ..SimulationConfig::default() };
+    let configs = [
+        base.clone(),
+        SimulationConfig { mixing_factor: 0.5, ..base.clone() },
+        SimulationConfig { mix_rule: MixRuleKind::PairwiseSwap, ..base.clone() },
+        SimulationConfig { shuffle_model: ShuffleModel::WindowedAverage { window: 4 }, ..base.clone() },
+        SimulationConfig { summation: Summation::Kahan, shuffles_per_round: 3, ..base.clone() },
+    ];
+    for config in &configs {
+        for fraction in [0.0, 0.25, 0.5] {
+            assert_fullest_cup_never_gains(&mut SimulationState::new(config, fraction, 0).unwrap());
+        }
+    }
+}
+
+#[test]
+fn fullest_cup_check_allows_negative_water() {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 8, repetitions: 1, seed: 5, ..SimulationConfig::default() };
+    let state = SimulationState::new(&config, 0.25, 0).unwrap();
+
+    // Fill every honest cup with negative water, which a snapshot can hold even though a configuration cannot
+    let mut snapshot = state.snapshot();
+    let corrupted = state.corrupted().to_vec();
+    for (index, water) in snapshot.water_cups[0].iter_mut().enumerate() {
+        *water = if corrupted[index] { 0.0 } else { -1.0 - (index % 3) as f64 };
+    }
+    snapshot.initial_water[0] = snapshot.water_cups[0].iter().sum::<f64>() + snapshot.lost[0];
+
+    assert_fullest_cup_never_gains(&mut SimulationState::from_snapshot(&config, &snapshot).unwrap());
+}
@@ -0,0 +1,24 @@
+//! Golden values of a small fixed-seed simulation, to catch accidental changes to its outcome.
+//!
+//! Any change to how the RNG is consumed (batch selection, corruption, seeding) changes these
+//! values. If such a change is intended, regenerate them by running `cargo test --test golden`, and replace
+//! the expected values in `GOLDEN` with the ones reported by the failing assertions.
+
+use aqua_shuffle_sim::{run_sim, SimulationConfig};
+
+/// Corruption threshold, success round, and sum of the per-repetition hiding rounds
+const GOLDEN: [(f64, usize, usize); 2] = [(0.1, 136, 3531), (0.3, 135, 3226)];
+
+#[test]
+fn simulation_matches_golden_values() {
+    let config = SimulationConfig { vector_length: 256, shuffle_size: 16, repetitions: 50, max_shuffles: 500, seed: 2024,
+                                    ..SimulationConfig::default() };
+    for (fraction, success_round, hiding_round_sum) in GOLDEN {
+        let result = run_sim(&config, fraction).unwrap();
+        assert_eq!(result.success_round, Some(success_round), "success round at {fraction}");
+
+        let rounds: Option<Vec<usize>> = result.hiding_rounds.into_iter().collect();
+        let rounds = rounds.unwrap_or_else(|| panic!("a repetition never hid the target at {fraction}"));
+        assert_eq!(rounds.iter().sum::<usize>(), hiding_round_sum, "sum of hiding rounds at {fraction}");
+    }
+}
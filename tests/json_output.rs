@@ -0,0 +1,57 @@
+use std::process::Command;
+
+use aqua_shuffle_sim::{run_sim, threshold_seed, SimulationConfig, SimulationResult};
+use serde_json::{Map, Value};
+
+/// Simulate 10% of 64 cups corrupted with `single --format json`, and return its record with its three objects merged
+fn merged_record() -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_aqua-shuffle-sim"))
+        .args(["single", "--corruption", "10", "--vector-length", "64", "--shuffle-size", "8", "--repetitions", "10",
+               "--max-shuffles", "200", "--seed", "3", "--format", "json", "--quiet"])
+        .output()
+        .expect("the simulator runs");
+    assert!(output.status.success(), "simulation failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let document: Value = serde_json::from_slice(&output.stdout).expect("the output is a JSON document");
+    let [record] = document["results"].as_array().expect("the document has results").as_slice() else {
+        panic!("a single threshold has a single record");
+    };
+    let mut merged = Map::new();
+    for part in ["params", "summary", "curve"] {
+        let fields = record[part].as_object().unwrap_or_else(|| panic!("the record has no `{part}`"));
+        for (name, value) in fields {
+            assert!(merged.insert(name.clone(), value.clone()).is_none(), "`{name}` is in more than one part of the record");
+        }
+    }
+    Value::Object(merged)
+}
+
+/// The threshold of [`merged_record`], simulated by the library
+fn simulated_result() -> SimulationResult {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 8, repetitions: 10, max_shuffles: 200, seed: threshold_seed(3, 0.1),
+                                    ..SimulationConfig::default() };
+    run_sim(&config, 0.1).unwrap()
+}
+
+#[test]
+fn json_record_merges_into_the_flat_result() {
+    let result = simulated_result();
+    let merged = merged_record();
+    assert_eq!(merged, serde_json::to_value(&result).unwrap(), "the record has other fields than the result");
+
+    let read: SimulationResult = serde_json::from_value(merged).expect("the record reads back into a result");
+    assert_eq!(read, result);
+}
+
+#[test]
+fn numbers_written_as_null_read_back_as_nan() {
+    let mut record = serde_json::to_value(simulated_result()).unwrap();
+    record["entropy"] = Value::Null;
+    record["secret_difference_in_round"][0] = Value::Null;
+    record["hiding_round_stats"]["variance"] = Value::Null;
+
+    let read: SimulationResult = serde_json::from_value(record).expect("the record reads back into a result");
+    assert!(read.entropy.is_nan());
+    assert!(read.secret_difference_in_round[0].is_nan());
+    assert!(read.hiding_round_stats.unwrap().variance.is_nan());
+}
@@ -0,0 +1,36 @@
+//! Hiding should get harder, not easier, as more cups are corrupted, which is a cheap guard against
+//! bugs such as corrupted cups taking part in the mixing.
+
+use aqua_shuffle_sim::{run_sim, threshold_seed, SimulationConfig};
+
+/// Corruption thresholds of the sweep, all of which still hide the target within `max_shuffles`
+const FRACTIONS: [f64; 4] = [0.0, 0.2, 0.4, 0.6];
+
+/// Number of seeds that the hiding rounds of every threshold are averaged over
+const SEEDS: u64 = 5;
+
+/// How many rounds earlier the averaged hiding round of a threshold may be than that of the threshold
+/// below it before it counts as an inversion, to allow for statistical noise
+const TOLERANCE: f64 = 3.0;
+
+#[test]
+fn hiding_round_does_not_decrease_with_corruption() {
+    // A fixed target level, as the default one grows with the corruption and so eases hiding. The
+    // mean over the repetitions is much less noisy than the success round, which is their maximum.
+    let config = SimulationConfig { vector_length: 256, shuffle_size: 16, repetitions: 30, max_shuffles: 1000,
+                                    target_eps: Some(4.0 / 256.0), ..SimulationConfig::default() };
+    let mean_hiding_rounds: Vec<f64> = FRACTIONS.iter().map(|&fraction| {
+        let total: f64 = (0..SEEDS).map(|seed| {
+            let config = SimulationConfig { seed: threshold_seed(seed, fraction), ..config.clone() };
+            let result = run_sim(&config, fraction).unwrap();
+            assert!(result.success_round.is_some(), "the target was not hidden in every repetition at {fraction} with seed {seed}");
+            result.hiding_round_stats.unwrap().mean
+        }).sum();
+        total / SEEDS as f64
+    }).collect();
+
+    for (fractions, rounds) in FRACTIONS.windows(2).zip(mean_hiding_rounds.windows(2)) {
+        assert!(rounds[1] >= rounds[0] - TOLERANCE, "hiding got easier from {} to {} corruption (mean hiding round {} vs {}), \
+                which is likely a bug", fractions[0], fractions[1], rounds[0], rounds[1]);
+    }
+}
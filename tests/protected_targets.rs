@@ -0,0 +1,26 @@
+use aqua_shuffle_sim::{trace_repetition, SimulationConfig};
+
+/// Check that heavy corruption never hits the targets, whichever cups they are.
+///
+/// With batches of a single cup no water ever moves, so the target still holds all of its water
+/// after the first round unless it was corrupted (which would zero it).
+fn assert_targets_protected(target_cups: Vec<usize>) {
+    for seed in 0..20 {
+        let config = SimulationConfig { vector_length: 64, shuffle_size: 1, max_shuffles: 1, repetitions: 1, seed,
+                                        target_cups: target_cups.clone(), ..SimulationConfig::default() };
+        let trace = trace_repetition(&config, 0.9).unwrap();
+        assert_eq!(trace, vec![1.0], "targets {target_cups:?} lost their water with seed {seed}");
+    }
+}
+
+#[test]
+fn single_target_is_never_corrupted() {
+    for target in 0..64 {
+        assert_targets_protected(vec![target]);
+    }
+}
+
+#[test]
+fn multiple_targets_are_never_corrupted() {
+    assert_targets_protected(vec![3, 17, 63]);
+}
@@ -0,0 +1,34 @@
+use aqua_shuffle_sim::{run_sim, run_sim_for_eps, SimulationConfig, SuccessCounting};
+
+/// Check that simulating the repetitions in chunks of every size gives the result of simulating them all at once
+fn assert_chunks_match(config: SimulationConfig, fraction: f64) {
+    let together = run_sim(&config, fraction).unwrap();
+    for chunk in [1, 3, 7] {
+        let chunked = run_sim(&SimulationConfig { repetition_chunk: Some(chunk), ..config.clone() }, fraction).unwrap();
+        assert_eq!(chunked, together, "chunks of {chunk} repetitions at {fraction}");
+    }
+}
+
+#[test]
+fn chunks_exit_in_the_same_round() {
+    let config = SimulationConfig { vector_length: 128, shuffle_size: 8, repetitions: 20, max_shuffles: 400, seed: 5, ..SimulationConfig::default() };
+    for fraction in [0.0, 0.2, 0.4] {
+        assert_chunks_match(config.clone(), fraction);
+    }
+}
+
+#[test]
+fn chunks_of_lasting_and_capped_runs_match() {
+    let config = SimulationConfig { vector_length: 128, shuffle_size: 8, repetitions: 20, max_shuffles: 60, seed: 5,
+                                    success_counting: SuccessCounting::StaysHidden, mixing_factor: 0.5, ..SimulationConfig::default() };
+    assert_chunks_match(config, 0.1);
+}
+
+#[test]
+fn chunks_match_for_every_target_level() {
+    let config = SimulationConfig { vector_length: 128, shuffle_size: 8, repetitions: 20, max_shuffles: 400, seed: 5, ..SimulationConfig::default() };
+    let levels = [0.05, 0.02, 0.01];
+    let together = run_sim_for_eps(&config, 0.1, &levels).unwrap();
+    let chunked = run_sim_for_eps(&SimulationConfig { repetition_chunk: Some(6), ..config }, 0.1, &levels).unwrap();
+    assert_eq!(chunked, together);
+}
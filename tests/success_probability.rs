@@ -0,0 +1,15 @@
+use aqua_shuffle_sim::{run_sim, SimulationConfig};
+
+#[test]
+fn success_round_does_not_decrease_with_the_probability() {
+    for seed in 0..5 {
+        let config = SimulationConfig { vector_length: 128, shuffle_size: 8, repetitions: 20, max_shuffles: 1000, seed, ..SimulationConfig::default() };
+        let round = |success_probability| {
+            let result = run_sim(&SimulationConfig { success_probability, ..config.clone() }, 0.1).unwrap();
+            result.success_round.expect("the targets are hidden within max_shuffles")
+        };
+        let (half, all) = (round(0.5), round(1.0));
+        assert!(half <= all, "half of the repetitions hid the targets in round {half} with seed {seed}, after all of them did in {all}");
+        assert_eq!(all, run_sim(&config, 0.1).unwrap().success_round.unwrap(), "a probability of 1 is not the default with seed {seed}");
+    }
+}
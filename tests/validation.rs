@@ -0,0 +1,17 @@
+use aqua_shuffle_sim::{run_sim, SimError, SimulationConfig};
+
+#[test]
+fn empty_shuffle_is_rejected() {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 0, ..SimulationConfig::default() };
+    assert_eq!(config.validate(), Err(SimError::EmptyShuffle));
+    assert_eq!(run_sim(&config, 0.1), Err(SimError::EmptyShuffle));
+}
+
+#[test]
+fn shuffle_larger_than_the_vector_is_rejected() {
+    let config = SimulationConfig { vector_length: 64, shuffle_size: 65, ..SimulationConfig::default() };
+    let error = SimError::ShuffleSizeTooLarge { shuffle_size: 65, vector_length: 64 };
+    assert_eq!(config.validate(), Err(error.clone()));
+    assert_eq!(run_sim(&config, 0.1), Err(error));
+    assert_eq!(SimulationConfig { shuffle_size: 64, ..config }.validate(), Ok(()));
+}